@@ -0,0 +1,76 @@
+// each board only uses some of the directions of its path macros
+#![allow(unused_macros)]
+use super::GameCoordinate;
+use crate::game::object::Collector;
+use crate::game::object::Spawner;
+use crate::game::state::State;
+use crate::tile::coordinate::Coordinate;
+use crate::tile::coordinate::HexCoordinate;
+use crate::tile::coordinate::HexDirection;
+use crate::tile::coordinate::TileType;
+
+// Rows are laid out in the odd-r convention of HexCoordinate, so a path
+// goes straight down by alternating hbr! and hbl!.
+
+macro_rules! hsr {
+    ($s: ident, $idx: ident) => {
+        ps!($s, $idx, HexDirection::Right);
+    };
+}
+
+macro_rules! htl {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, HexDirection::TopLeft);
+    };
+}
+
+macro_rules! htr {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, HexDirection::TopRight);
+    };
+}
+
+macro_rules! hr {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, HexDirection::Right);
+    };
+}
+
+macro_rules! hbr {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, HexDirection::BottomRight);
+    };
+}
+
+macro_rules! hbl {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, HexDirection::BottomLeft);
+    };
+}
+
+macro_rules! hl {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, HexDirection::Left);
+    };
+}
+
+/// Order of the features accepted by the mint command
+pub fn standard_directions() -> Vec<HexDirection> {
+    HexCoordinate::directions()
+}
+
+/// Map of each arena, the arena id is the index in this list
+pub const MAPS: &[fn(&mut State<GameCoordinate>) -> usize] = &[init_map];
+
+pub fn init_map(s: &mut State<GameCoordinate>) -> usize {
+    let mut m = 0;
+    em!(s, m); hsr!(s, m); hr!(s, m); hr!(s, m); hr!(s, m); hr!(s, m); hr!(s, m); hr!(s, m); hr!(s, m); hr!(s, m); hbr!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); hbl!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); hbl!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); hbl!(s, m); hl!(s, m); hl!(s, m); hl!(s, m); hl!(s, m); hl!(s, m); hl!(s, m); hl!(s, m); em!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); hbr!(s, m); pm!(s, m); pm!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m);
+    em!(s, m); bk!(s, m); hbr!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); em!(s, m); hr!(s, m); hr!(s, m); hr!(s, m); hr!(s, m); hr!(s, m); hr!(s, m); hr!(s, m); hbr!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); pc!(s, m); rs!(s, m);
+    m
+}
@@ -1,33 +1,121 @@
-#![allow(unused_macros)]
-use crate::game::object::Collector;
 use crate::game::object::Monster;
-use crate::game::object::Spawner;
 use crate::game::state::State;
 use crate::settlement::SettlementInfo;
-use crate::tile::map::Map;
-use zkwasm_rust_sdk::require;
 //use crate::game::object::Dropped;
 //use crate::tile::map::Map;
 use crate::game::object::Tower;
-use crate::tile::coordinate::RectDirection;
-use crate::tile::coordinate::Tile;
+use crate::tile::coordinate::Coordinate;
 use serde::Serialize;
 
+#[cfg(all(feature = "hex", feature = "rect8"))]
+compile_error!("features `hex` and `rect8` select different boards and are mutually exclusive");
+
+#[cfg(not(any(feature = "hex", feature = "rect8")))]
+pub type GameCoordinate = crate::tile::coordinate::RectCoordinate;
+#[cfg(feature = "hex")]
+pub type GameCoordinate = crate::tile::coordinate::HexCoordinate;
+#[cfg(feature = "rect8")]
+pub type GameCoordinate = crate::tile::coordinate::Rect8Coordinate;
+
+pub type GameDirection = <GameCoordinate as Coordinate>::Direction;
+
 pub const SPWAN_INTERVAL:u64 = 3;
 
+
 const MONSTER_LEVEL: [[u64; 3]; 3] = [
     [30, 1, 2],
     [30, 1, 10],
     [30, 1, 50],
 ];
 
-const TOWER_LEVEL: [[u64; 3]; 3] = [
-    [3, 1, 3],
-    [5, 3, 2],
-    [7, 10, 1],
+#[derive(Serialize, Clone)]
+pub struct TowerLevel {
+    pub range: u64,
+    pub power: u64,
+    pub cooldown: u64,
+    pub upgrade_cost: u64, // cost to reach the next level, 0 at max level
+}
+
+/// Stats of standard towers, the first entry is level 1
+const TOWER_LEVELS: [TowerLevel; 3] = [
+    TowerLevel { range: 5, power: 3, cooldown: 2, upgrade_cost: 1500 },
+    TowerLevel { range: 7, power: 10, cooldown: 1, upgrade_cost: 8000 },
+    TowerLevel { range: 9, power: 25, cooldown: 1, upgrade_cost: 0 },
 ];
 
-pub const UPGRADE_COST: [u64; 2] = [1500, 8000];
+pub const MAX_LEVEL: u64 = TOWER_LEVELS.len() as u64;
+
+pub fn tower_level(lvl: u64) -> Option<&'static TowerLevel> {
+    if lvl == 0 {
+        None
+    } else {
+        TOWER_LEVELS.get((lvl - 1) as usize)
+    }
+}
+
+/// Cost to upgrade a tower from lvl to lvl + 1, None if lvl can not be upgraded
+pub fn upgrade_cost(lvl: u64) -> Option<u64> {
+    if lvl < MAX_LEVEL {
+        tower_level(lvl).map(|l| l.upgrade_cost)
+    } else {
+        None
+    }
+}
+
+pub const TOWER_KIND_STANDARD: u64 = 0;
+pub const TOWER_KIND_SNIPER: u64 = 1;
+pub const TOWER_KIND_RAPID: u64 = 2;
+
+/// Recipe 0 levels a standard tower up along TOWER_LEVELS,
+/// recipe n (n > 0) is the n-th entry of Config::recipes
+pub const RECIPE_LEVEL_UP: u64 = 0;
+
+#[derive(Serialize, Clone)]
+pub struct UpgradeRecipe {
+    pub source_kind: u64,
+    pub source_lvl: u64,
+    pub target_kind: u64,
+    pub target_lvl: u64,
+    pub range: u64,
+    pub power: u64,
+    pub cooldown: u64,
+    pub cost: u64,
+}
+
+impl UpgradeRecipe {
+    pub fn applies_to<D: Clone + Serialize>(&self, t: &Tower<D>) -> bool {
+        t.kind == self.source_kind && t.lvl == self.source_lvl
+    }
+    pub fn apply<D: Clone + Serialize>(&self, t: &mut Tower<D>) {
+        t.kind = self.target_kind;
+        t.lvl = self.target_lvl;
+        t.range = self.range;
+        t.power = self.power;
+        t.cooldown = self.cooldown;
+    }
+}
+
+// [source kind, source lvl, target kind, target lvl, range, power, cooldown, cost]
+// branched kinds level on through their own recipes, past the standard max level
+const UPGRADE_RECIPES: [[u64; 8]; 4] = [
+    [TOWER_KIND_STANDARD, 2, TOWER_KIND_SNIPER, 3, 9, 8, 3, 8000],
+    [TOWER_KIND_STANDARD, 2, TOWER_KIND_RAPID, 3, 4, 4, 0, 8000],
+    [TOWER_KIND_SNIPER, 3, TOWER_KIND_SNIPER, 4, 11, 16, 3, 20000],
+    [TOWER_KIND_RAPID, 3, TOWER_KIND_RAPID, 4, 5, 8, 0, 20000],
+];
+
+fn build_recipe(r: &[u64; 8]) -> UpgradeRecipe {
+    UpgradeRecipe {
+        source_kind: r[0],
+        source_lvl: r[1],
+        target_kind: r[2],
+        target_lvl: r[3],
+        range: r[4],
+        power: r[5],
+        cooldown: r[6],
+        cost: r[7],
+    }
+}
 
 pub fn spawn_monster(count: u64) -> Monster {
     if count % 10 == 0 {
@@ -39,26 +127,120 @@ pub fn spawn_monster(count: u64) -> Monster {
     }
 }
 
-pub fn build_tower(lvl: u64, dir: RectDirection) -> Tower<RectDirection> {
-    let l = TOWER_LEVEL[lvl as usize];
-    Tower::new(lvl, l[0], l[1], l[2], [0, 0], dir)
+pub fn build_tower<D: Clone + Serialize>(lvl: u64, dir: D) -> Tower<D> {
+    let l = tower_level(lvl).unwrap();
+    Tower::new(TOWER_KIND_STANDARD, lvl, l.range, l.power, l.cooldown, [0, 0], dir)
 }
 
-pub fn upgrade_tower(t: &mut Tower<RectDirection>) {
-    unsafe { require(t.lvl < TOWER_LEVEL.len() as u64) };
+/// Moves the tower to the stats of the next level, false if it is at max level
+pub fn upgrade_tower<D: Clone + Serialize>(t: &mut Tower<D>) -> bool {
+    if upgrade_cost(t.lvl).is_none() {
+        return false;
+    }
     t.lvl = t.lvl + 1;
-    let l = TOWER_LEVEL[t.lvl as usize];
-    t.range = l[0];
-    t.power = l[1];
-    t.cooldown = l[2];
+    let l = tower_level(t.lvl).unwrap();
+    t.range = l.range;
+    t.power = l.power;
+    t.cooldown = l.cooldown;
+    true
+}
+
+/// Protocol fee of marketplace sales in basis points
+pub const MARKET_FEE_BPS: u64 = 250;
+/// Listings the market holds at the same time, all of them are stored in one record
+pub const MAX_LISTINGS: u64 = 256;
+
+/// Withdrawal caps apply per window of this many ticks
+pub const WITHDRAW_WINDOW: u64 = 100;
+pub const PLAYER_WITHDRAW_CAP: u64 = 10000;
+pub const GLOBAL_WITHDRAW_CAP: u64 = 100000;
+/// Withdrawals beyond this many queued ones fail instead of growing the queue
+pub const MAX_PENDING_WITHDRAWALS: u64 = 64;
+
+/// Delegations active at the same time, all of them are stored in one record
+pub const MAX_DELEGATIONS: u64 = 256;
+
+/// Number of entries kept in each leaderboard
+pub const LEADERBOARD_SIZE: u64 = 10;
+
+/// A wave ends with every tenth monster of a spawner
+pub const WAVE_SIZE: u64 = 10;
+
+pub const ACHIEVEMENT_KILLS: u64 = 0; // kills of all towers of a player
+pub const ACHIEVEMENT_TOWER_KILLS: u64 = 1; // kills of a single tower
+pub const ACHIEVEMENT_TOWER_LEVEL: u64 = 2; // level of an upgraded tower
+pub const ACHIEVEMENT_WAVE: u64 = 3; // wave reached with a tower on the board
+
+#[derive(Serialize, Clone)]
+pub struct Achievement {
+    pub kind: u64,
+    pub threshold: u64,
+    pub bonus: u64, // reward granted once on unlock, 0 for none
 }
 
+// [kind, threshold, bonus], the index is the bit in PlayerData::achievements
+const ACHIEVEMENTS: [[u64; 3]; 4] = [
+    [ACHIEVEMENT_KILLS, 1, 100],
+    [ACHIEVEMENT_TOWER_KILLS, 1000, 5000],
+    [ACHIEVEMENT_TOWER_LEVEL, MAX_LEVEL, 1000],
+    [ACHIEVEMENT_WAVE, 50, 2000],
+];
+
+/// Share of the upgrade spend refunded when a tower is burnt
+pub const BURN_REFUND_PERCENT: u64 = 50;
+
+/// Reward a placed tower pays every `RENT_INTERVAL` ticks to keep its tile, 0 disables the rent
+pub const TOWER_RENT: u64 = 1;
+pub const RENT_INTERVAL: u64 = 5;
+/// Rent payments a tower can owe before it is removed from the board
+pub const RENT_GRACE: u64 = 10;
+
+/// Ticks an auction of a premium tile runs after the first bid
+pub const AUCTION_TICKS: u64 = 20;
+/// Ticks the winner of an auction keeps the placement rights
+pub const LEASE_TICKS: u64 = 500;
+/// Auctions and leases running at the same time, all of them are stored in one record
+pub const MAX_AUCTIONS: u64 = 256;
+
+/// Towers one owner can have on a board at the same time
+pub const MAX_TOWERS_PER_BOARD: u64 = 6;
+/// Smallest distance between two towers of the same owner on a board
+pub const MIN_TOWER_DISTANCE: u64 = 2;
+
+/// Towers a player can hold, every tower adds a word to the stored player
+pub const INVENTORY_CAP: u64 = 256;
+/// Inventory entries returned per page by the state query
+pub const INVENTORY_PAGE_SIZE: u64 = 32;
+
 pub const UPGRADE_MODIFIER: u64 = 5;
 pub const UPGRADE_COST_MODIFIER: u64 = 2;
 
 #[derive(Serialize, Clone)]
 pub struct Config {
-    pub standard_towers: [Tower<RectDirection>; 4],
+    pub standard_towers: Vec<Tower<GameDirection>>,
+    pub recipes: Vec<UpgradeRecipe>,
+    pub tower_levels: Vec<TowerLevel>,
+    pub max_level: u64,
+    pub market_fee_bps: u64,
+    pub max_listings: u64,
+    pub withdraw_window: u64,
+    pub player_withdraw_cap: u64,
+    pub global_withdraw_cap: u64,
+    pub max_pending_withdrawals: u64,
+    pub max_delegations: u64,
+    pub leaderboard_size: u64,
+    pub achievements: Vec<Achievement>,
+    pub burn_refund_percent: u64,
+    pub inventory_cap: u64,
+    pub inventory_page_size: u64,
+    pub tower_rent: u64,
+    pub rent_interval: u64,
+    pub rent_grace: u64,
+    pub auction_ticks: u64,
+    pub lease_ticks: u64,
+    pub max_auctions: u64,
+    pub max_towers_per_board: u64,
+    pub min_tower_distance: u64,
 }
 
 impl Config {
@@ -70,174 +252,272 @@ impl Config {
     }
 }
 
+/// Operator key of builds without ADMIN_PUBKEY, it is not a valid public key so that
+/// nobody can sign operator commands
+const DEFAULT_ADMIN_PUBKEY: [u64; 4] = [0; 4];
+
+/// Reads the operator key from 64 hex digits, the 32 bytes hold the four limbs in little endian
+fn admin_pubkey(hex: Option<&str>) -> [u64; 4] {
+    let hex = match hex {
+        Some(hex) => hex.trim_start_matches("0x"),
+        None => return DEFAULT_ADMIN_PUBKEY,
+    };
+    assert!(hex.len() == 64, "ADMIN_PUBKEY must be 32 bytes in hex");
+    let mut pkey = [0; 4];
+    for (limb, chunk) in pkey.iter_mut().zip(hex.as_bytes().chunks(16)) {
+        let digits = std::str::from_utf8(chunk).unwrap();
+        // the digits read as a big endian number, swapped to the little endian limb
+        *limb = u64::from_str_radix(digits, 16).expect("ADMIN_PUBKEY must be hex").swap_bytes();
+    }
+    pkey
+}
+
 lazy_static::lazy_static! {
+    /// Public key of the operator that relays deposits from L1 and collects the fees,
+    /// taken from the ADMIN_PUBKEY environment variable at compile time
+    pub static ref ADMIN_PUBKEY: [u64; 4] = admin_pubkey(option_env!("ADMIN_PUBKEY"));
     pub static ref CONFIG: Config = Config {
-        standard_towers: [
-            build_tower(1, RectDirection::Top),
-            build_tower(1, RectDirection::Left),
-            build_tower(1, RectDirection::Right),
-            build_tower(1, RectDirection::Bottom)
-        ],
+        standard_towers: layout::standard_directions()
+            .into_iter()
+            .map(|dir| build_tower(1, dir))
+            .collect(),
+        recipes: UPGRADE_RECIPES.iter().map(build_recipe).collect(),
+        tower_levels: TOWER_LEVELS.to_vec(),
+        max_level: MAX_LEVEL,
+        market_fee_bps: MARKET_FEE_BPS,
+        max_listings: MAX_LISTINGS,
+        withdraw_window: WITHDRAW_WINDOW,
+        player_withdraw_cap: PLAYER_WITHDRAW_CAP,
+        global_withdraw_cap: GLOBAL_WITHDRAW_CAP,
+        max_pending_withdrawals: MAX_PENDING_WITHDRAWALS,
+        max_delegations: MAX_DELEGATIONS,
+        leaderboard_size: LEADERBOARD_SIZE,
+        achievements: ACHIEVEMENTS
+            .iter()
+            .map(|a| Achievement { kind: a[0], threshold: a[1], bonus: a[2] })
+            .collect(),
+        burn_refund_percent: BURN_REFUND_PERCENT,
+        inventory_cap: INVENTORY_CAP,
+        inventory_page_size: INVENTORY_PAGE_SIZE,
+        tower_rent: TOWER_RENT,
+        rent_interval: RENT_INTERVAL,
+        rent_grace: RENT_GRACE,
+        auction_ticks: AUCTION_TICKS,
+        lease_ticks: LEASE_TICKS,
+        max_auctions: MAX_AUCTIONS,
+        max_towers_per_board: MAX_TOWERS_PER_BOARD,
+        min_tower_distance: MIN_TOWER_DISTANCE,
     };
 }
 
 const WIDTH: usize = 12;
 const HEIGHT: usize = 8;
 
-pub static mut GLOBAL: State = State {
-    id_allocator: 0,
-    map: Map {
-        width: WIDTH,
-        height: HEIGHT,
-        tiles: vec![],
-    },
-    spawners: vec![],
-    towers: vec![],
-    collectors: vec![],
-    drops: vec![],
-    monsters: vec![],
-    events: vec![],
-};
+/// Every arena runs its own board, indexed by arena id
+pub static mut ARENAS: Vec<State<GameCoordinate>> = vec![];
 
 pub fn cor_to_index(x: usize, y: usize) -> usize {
     x + y * WIDTH
 }
 
-macro_rules! pb {
-    ($idx: ident) => {
-        let global = unsafe { &mut GLOBAL };
-        let spawner = Spawner::new(0, 3);
-        let cor = global.map.coordinate_of_tile_index($idx);
-        global.place_spawner_at(spawner, cor);
-        global.map.set_feature($idx, Some(RectDirection::Bottom));
-        $idx += 1;
-    };
-}
-
-macro_rules! pt {
-    ($idx: ident) => {
-        let global = unsafe { &mut GLOBAL };
+// spawner
+macro_rules! ps {
+    ($s: ident, $idx: ident, $dir: expr) => {
         let spawner = Spawner::new(0, 3);
-        let cor = Map::coordinate_of_tile_index($idx);
-        global.place_spawner_at(spawner, cor);
-        global.map.set_feature($idx, Some(RectDirection::Top));
+        let cor = $s.map.coordinate_of_tile_index($idx);
+        $s.place_spawner_at(spawner, cor);
+        $s.map.set_feature($idx, Some($dir));
+        $s.map.set_tile_type($idx, TileType::Path);
         $idx += 1;
     };
 }
 
-macro_rules! pl {
-    ($idx: ident) => {
-        let global = unsafe { &mut GLOBAL };
-        let spawner = Spawner::new(0, 3);
-        let cor = Map::coordinate_of_tile_index($idx);
-        global.place_spawner_at(spawner, cor);
-        global.map.set_feature($idx, Some(RectDirection::Left));
-        $idx += 1;
-    };
-}
-
-macro_rules! pr {
-    ($idx: ident) => {
-        let global = unsafe { &mut GLOBAL };
-        let spawner = Spawner::new(0, 3);
-        let cor = Map::coordinate_of_tile_index($idx);
-        global.place_spawner_at(spawner, cor);
-        global.map.set_feature($idx, Some(RectDirection::Right));
+// path
+macro_rules! pa {
+    ($s: ident, $idx: ident, $dir: expr) => {
+        $s.map.set_feature($idx, Some($dir));
+        $s.map.set_tile_type($idx, TileType::Path);
         $idx += 1;
     };
 }
 
-
+// collector
 macro_rules! pc {
-    ($idx: ident) => {
-        let global = unsafe { &mut GLOBAL };
+    ($s: ident, $idx: ident) => {
         let collector = Collector::new(5);
-        let cor = global.map.coordinate_of_tile_index($idx);
-        global.place_collector_at(collector, cor);
-        $idx += 1;
-    };
-}
-
-
-
-macro_rules! mb {
-    ($idx: ident) => {
-        let global = unsafe { &mut GLOBAL };
-        global.map.set_feature($idx, Some(RectDirection::Bottom));
+        let cor = $s.map.coordinate_of_tile_index($idx);
+        $s.place_collector_at(collector, cor);
+        $s.map.set_tile_type($idx, TileType::Path);
         $idx += 1;
     };
 }
 
-macro_rules! mt {
-    ($idx: ident) => {
-        let global = unsafe { &mut GLOBAL };
-        global.map.set_feature($idx, Some(RectDirection::Top));
+// blocked
+macro_rules! bk {
+    ($s: ident, $idx: ident) => {
+        $s.map.set_tile_type($idx, TileType::Blocked);
         $idx += 1;
     };
 }
 
-macro_rules! ml {
-    ($idx: ident) => {
-        let global = unsafe { &mut GLOBAL };
-        global.map.set_feature($idx, Some(RectDirection::Left));
+// reserved
+macro_rules! rs {
+    ($s: ident, $idx: ident) => {
+        $s.map.set_tile_type($idx, TileType::Reserved);
         $idx += 1;
     };
 }
 
-macro_rules! mr {
-    ($idx: ident) => {
-        let global = unsafe { &mut GLOBAL };
-        global.map.set_feature($idx, Some(RectDirection::Right));
+// premium
+macro_rules! pm {
+    ($s: ident, $idx: ident) => {
+        $s.map.set_tile_type($idx, TileType::Premium);
         $idx += 1;
     };
 }
 
 // empty
 macro_rules! em {
-    ($idx:ident) => {
+    ($s: ident, $idx:ident) => {
         $idx += 1;
     };
 }
 
+#[cfg(not(any(feature = "hex", feature = "rect8")))]
+mod rect;
+#[cfg(not(any(feature = "hex", feature = "rect8")))]
+use rect as layout;
+
+#[cfg(feature = "hex")]
+mod hex;
+#[cfg(feature = "hex")]
+use hex as layout;
+
+#[cfg(feature = "rect8")]
+mod rect8;
+#[cfg(feature = "rect8")]
+use rect8 as layout;
+
 pub fn init_state() {
-    let global = unsafe { &mut GLOBAL };
-    if global.map.tiles.is_empty() {
-        for _ in 0..96 {
-            global
-                .map
-                .tiles
-                .push(Tile::new(None))
+    let arenas = unsafe { &mut ARENAS };
+    if arenas.is_empty() {
+        for (id, init_map) in layout::MAPS.iter().enumerate() {
+            let mut arena = State::new(id as u64, WIDTH, HEIGHT);
+            // tiles and collectors are static, the stored spawners,
+            // towers and id allocator replace the initial ones
+            let m = init_map(&mut arena);
+            arena.fetch();
+            //zkwasm_rust_sdk::dbg!("m is {}\n", m);
+            if m != WIDTH * HEIGHT {
+                unreachable!();
+            }
+            arenas.push(arena);
         }
     }
-    let stored = global.fetch();
-    if !stored {
-        let mut m = 0;
-        em!(m); em!(m); pb!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); pc!(m); em!(m);
-        em!(m); em!(m); mb!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); mt!(m); em!(m);
-        em!(m); em!(m); mb!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); mt!(m); em!(m);
-        em!(m); em!(m); mr!(m); mr!(m); mr!(m); mr!(m); mr!(m); mb!(m); em!(m); em!(m); mt!(m); em!(m);
-        em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); mb!(m); em!(m); em!(m); mt!(m); em!(m);
-        em!(m); em!(m); em!(m); em!(m); mb!(m); ml!(m); ml!(m); ml!(m); em!(m); em!(m); mt!(m); em!(m);
-        em!(m); em!(m); em!(m); em!(m); mb!(m); em!(m); em!(m); em!(m); em!(m); em!(m); mt!(m); em!(m);
-        em!(m); em!(m); em!(m); em!(m); mr!(m); mr!(m); mr!(m); mr!(m); mr!(m); mr!(m); mt!(m); em!(m);
-        //zkwasm_rust_sdk::dbg!("m is {}\n", m);
-
-        if m != WIDTH * HEIGHT {
-            unreachable!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::admin_pubkey;
+    use super::build_tower;
+    use super::tower_level;
+    use super::upgrade_cost;
+    use super::upgrade_tower;
+    use super::init_state;
+    use super::layout;
+    use super::ARENAS;
+    use super::Config;
+    use super::DEFAULT_ADMIN_PUBKEY;
+    use super::GameCoordinate;
+    use super::CONFIG;
+    use super::HEIGHT;
+    use super::MAX_LEVEL;
+    use super::TOWER_KIND_STANDARD;
+    use super::WIDTH;
+    use crate::game::state::State;
+    use crate::tile::coordinate::Coordinate;
+    use crate::tile::coordinate::TileType;
+
+    #[test]
+    fn test_every_level_reachable() {
+        let mut tower = build_tower(1, GameCoordinate::directions()[0].clone());
+        let mut spent = 0;
+        while let Some(cost) = upgrade_cost(tower.lvl) {
+            spent += cost;
+            assert!(upgrade_tower(&mut tower));
+            let l = tower_level(tower.lvl).unwrap();
+            assert_eq!(tower.range, l.range);
+            assert_eq!(tower.power, l.power);
+            assert_eq!(tower.cooldown, l.cooldown);
         }
-    } else {
-        let mut m = 0;
-        em!(m); em!(m); mb!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m);
-        em!(m); em!(m); mb!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); mt!(m); em!(m);
-        em!(m); em!(m); mb!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); mt!(m); em!(m);
-        em!(m); em!(m); mr!(m); mr!(m); mr!(m); mr!(m); mr!(m); mb!(m); em!(m); em!(m); mt!(m); em!(m);
-        em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); em!(m); mb!(m); em!(m); em!(m); mt!(m); em!(m);
-        em!(m); em!(m); em!(m); em!(m); mb!(m); ml!(m); ml!(m); ml!(m); em!(m); em!(m); mt!(m); em!(m);
-        em!(m); em!(m); em!(m); em!(m); mb!(m); em!(m); em!(m); em!(m); em!(m); em!(m); mt!(m); em!(m);
-        em!(m); em!(m); em!(m); em!(m); mr!(m); mr!(m); mr!(m); mr!(m); mr!(m); mr!(m); mt!(m); em!(m);
-        //zkwasm_rust_sdk::dbg!("m is {}\n", m);
-        if m != WIDTH * HEIGHT {
-            unreachable!();
+        assert_eq!(tower.lvl, MAX_LEVEL);
+        assert_eq!(spent, 1500 + 8000);
+        assert!(!upgrade_tower(&mut tower));
+        assert_eq!(tower.lvl, MAX_LEVEL);
+    }
+
+    #[test]
+    fn test_upgrade_never_panics() {
+        for lvl in 0..MAX_LEVEL + 3 {
+            let mut tower = build_tower(1, GameCoordinate::directions()[0].clone());
+            tower.lvl = lvl;
+            let upgradable = lvl >= 1 && lvl < MAX_LEVEL;
+            assert_eq!(upgrade_cost(lvl).is_some(), upgradable);
+            assert_eq!(upgrade_tower(&mut tower), upgradable);
         }
     }
+
+    #[test]
+    fn test_recipes_reachable() {
+        // every recipe starts from a standard level or from the result of another recipe
+        for r in CONFIG.recipes.iter() {
+            assert_eq!(r.target_lvl, r.source_lvl + 1);
+            if r.source_kind == TOWER_KIND_STANDARD {
+                assert!(tower_level(r.source_lvl).is_some());
+            } else {
+                assert!(CONFIG
+                    .recipes
+                    .iter()
+                    .any(|p| p.target_kind == r.source_kind && p.target_lvl == r.source_lvl));
+            }
+        }
+        let config = Config::to_json_string();
+        assert!(config.contains("\"tower_levels\""));
+        assert!(config.contains("\"max_level\":3"));
+    }
+
+    #[test]
+    fn test_maps_use_every_tile_type() {
+        for init_map in layout::MAPS.iter() {
+            let mut arena = State::<GameCoordinate>::new(0, WIDTH, HEIGHT);
+            assert_eq!(init_map(&mut arena), WIDTH * HEIGHT);
+            for t in [TileType::Buildable, TileType::Path, TileType::Blocked, TileType::Reserved] {
+                assert!(arena.map.tiles.iter().any(|tile| tile.tile_type == t));
+            }
+        }
+    }
+
+    #[test]
+    fn test_restart_keeps_id_allocator() {
+        let _guard = crate::game::testing::setup();
+        let arenas = unsafe { &mut ARENAS };
+        let allocated = arenas.iter().map(|a| a.id_allocator).collect::<Vec<_>>();
+        for arena in arenas.iter() {
+            assert!(arena.collectors.iter().all(|c| c.id <= arena.id_allocator));
+            arena.store();
+        }
+        arenas.clear();
+        init_state();
+        let arenas = unsafe { &ARENAS };
+        assert_eq!(arenas.iter().map(|a| a.id_allocator).collect::<Vec<_>>(), allocated);
+    }
+
+    #[test]
+    fn test_admin_pubkey() {
+        assert_eq!(admin_pubkey(None), DEFAULT_ADMIN_PUBKEY);
+        let hex = "0100000000000000ff000000000000000000000000000080efcdab8967452301";
+        let pkey = [1, 0xff, 1 << 63, 0x0123456789abcdef];
+        assert_eq!(admin_pubkey(Some(hex)), pkey);
+        assert_eq!(admin_pubkey(Some(&format!("0x{}", hex))), pkey);
+    }
 }
@@ -0,0 +1,95 @@
+// each board only uses some of the directions of its path macros
+#![allow(unused_macros)]
+use super::GameCoordinate;
+use crate::game::object::Collector;
+use crate::game::object::Spawner;
+use crate::game::state::State;
+use crate::tile::coordinate::RectDirection;
+use crate::tile::coordinate::TileType;
+
+macro_rules! pb {
+    ($s: ident, $idx: ident) => {
+        ps!($s, $idx, RectDirection::Bottom);
+    };
+}
+
+macro_rules! pt {
+    ($s: ident, $idx: ident) => {
+        ps!($s, $idx, RectDirection::Top);
+    };
+}
+
+macro_rules! pl {
+    ($s: ident, $idx: ident) => {
+        ps!($s, $idx, RectDirection::Left);
+    };
+}
+
+macro_rules! pr {
+    ($s: ident, $idx: ident) => {
+        ps!($s, $idx, RectDirection::Right);
+    };
+}
+
+macro_rules! mb {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, RectDirection::Bottom);
+    };
+}
+
+macro_rules! mt {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, RectDirection::Top);
+    };
+}
+
+macro_rules! ml {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, RectDirection::Left);
+    };
+}
+
+macro_rules! mr {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, RectDirection::Right);
+    };
+}
+
+/// Order of the features accepted by the mint command
+pub fn standard_directions() -> Vec<RectDirection> {
+    vec![
+        RectDirection::Top,
+        RectDirection::Left,
+        RectDirection::Right,
+        RectDirection::Bottom,
+    ]
+}
+
+/// Map of each arena, the arena id is the index in this list
+pub const MAPS: &[fn(&mut State<GameCoordinate>) -> usize] = &[init_map, init_snake_map];
+
+pub fn init_map(s: &mut State<GameCoordinate>) -> usize {
+    let mut m = 0;
+    em!(s, m); em!(s, m); pb!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); pc!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); mb!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); mt!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); mb!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); mt!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mb!(s, m); em!(s, m); em!(s, m); mt!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); mb!(s, m); pm!(s, m); pm!(s, m); mt!(s, m); em!(s, m);
+    em!(s, m); bk!(s, m); em!(s, m); em!(s, m); mb!(s, m); ml!(s, m); ml!(s, m); ml!(s, m); em!(s, m); em!(s, m); mt!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); em!(s, m); em!(s, m); mb!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); mt!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); em!(s, m); em!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mt!(s, m); rs!(s, m);
+    m
+}
+
+pub fn init_snake_map(s: &mut State<GameCoordinate>) -> usize {
+    let mut m = 0;
+    pr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mb!(s, m);
+    em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); mb!(s, m);
+    em!(s, m); mb!(s, m); ml!(s, m); ml!(s, m); ml!(s, m); ml!(s, m); ml!(s, m); ml!(s, m); ml!(s, m); ml!(s, m); ml!(s, m); ml!(s, m);
+    em!(s, m); mb!(s, m); pm!(s, m); pm!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m);
+    em!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mb!(s, m); em!(s, m);
+    bk!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); mb!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); mb!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); pc!(s, m); rs!(s, m);
+    m
+}
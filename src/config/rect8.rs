@@ -0,0 +1,85 @@
+// each board only uses some of the directions of its path macros
+#![allow(unused_macros)]
+use super::GameCoordinate;
+use crate::game::object::Collector;
+use crate::game::object::Spawner;
+use crate::game::state::State;
+use crate::tile::coordinate::Coordinate;
+use crate::tile::coordinate::Rect8Coordinate;
+use crate::tile::coordinate::Rect8Direction;
+use crate::tile::coordinate::TileType;
+
+macro_rules! pbr {
+    ($s: ident, $idx: ident) => {
+        ps!($s, $idx, Rect8Direction::BottomRight);
+    };
+}
+
+macro_rules! mt {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, Rect8Direction::Top);
+    };
+}
+
+macro_rules! mtr {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, Rect8Direction::TopRight);
+    };
+}
+
+macro_rules! mr {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, Rect8Direction::Right);
+    };
+}
+
+macro_rules! mbr {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, Rect8Direction::BottomRight);
+    };
+}
+
+macro_rules! mb {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, Rect8Direction::Bottom);
+    };
+}
+
+macro_rules! mbl {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, Rect8Direction::BottomLeft);
+    };
+}
+
+macro_rules! ml {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, Rect8Direction::Left);
+    };
+}
+
+macro_rules! mtl {
+    ($s: ident, $idx: ident) => {
+        pa!($s, $idx, Rect8Direction::TopLeft);
+    };
+}
+
+/// Order of the features accepted by the mint command
+pub fn standard_directions() -> Vec<Rect8Direction> {
+    Rect8Coordinate::directions()
+}
+
+/// Map of each arena, the arena id is the index in this list
+pub const MAPS: &[fn(&mut State<GameCoordinate>) -> usize] = &[init_map];
+
+pub fn init_map(s: &mut State<GameCoordinate>) -> usize {
+    let mut m = 0;
+    pbr!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m);
+    em!(s, m); mbr!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); mb!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); mbr!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); mtr!(s, m); mb!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); em!(s, m); mbr!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); mtr!(s, m); pm!(s, m); mb!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); em!(s, m); em!(s, m); mr!(s, m); mr!(s, m); mr!(s, m); mtr!(s, m); em!(s, m); pm!(s, m); mb!(s, m); em!(s, m);
+    em!(s, m); bk!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); mb!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); mb!(s, m); em!(s, m);
+    em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); em!(s, m); pc!(s, m); rs!(s, m);
+    m
+}
@@ -3,7 +3,6 @@ use crate::player::{TDPlayer, Owner};
 use object::to_full_obj_id;
 use serde::{Serialize, Serializer};
 use zkwasm_rust_sdk::require;
-use crate::settlement::SettlementInfo;
 
 // Custom serializer for `u64` as a string.
 pub fn bigint_serializer<S>(value: &u64, serializer: S) -> Result<S::Ok, S::Error>
@@ -15,10 +14,21 @@ where
 
 
 
+pub mod achievement;
+pub mod auction;
+pub mod bridge;
+pub mod delegation;
 pub mod event;
+pub mod guild;
+pub mod leaderboard;
+pub mod market;
+pub mod metadata;
 pub mod object;
 pub mod serialize;
 pub mod state;
+#[cfg(test)]
+pub mod testing;
+pub mod withdrawal;
 
 // This is a standalone game state manipulate module that connets with UI
 // controllers and model handlers
@@ -31,6 +41,35 @@ const CMD_DROP_TOWER: u64 = 4;
 const CMD_UPGRADE_TOWER: u64 = 5;
 const CMD_COLLECT_REWARDS: u64 = 6;
 const CMD_WITHDRAW_REWARDS: u64 = 7;
+const CMD_TRANSFER_TOWER: u64 = 8;
+const CMD_LIST_TOWER: u64 = 9;
+const CMD_CANCEL_LISTING: u64 = 10;
+const CMD_BUY_TOWER: u64 = 11;
+const CMD_DELEGATE_TOWER: u64 = 12;
+const CMD_COLLECT_ALL_REWARDS: u64 = 13;
+const CMD_SET_AUTO_COLLECT: u64 = 14;
+const CMD_CREATE_GUILD: u64 = 15;
+const CMD_JOIN_GUILD: u64 = 16;
+const CMD_DEPOSIT_GUILD: u64 = 17;
+const CMD_PROMOTE_OFFICER: u64 = 18;
+const CMD_DONATE_TOWER: u64 = 19;
+const CMD_UPGRADE_GUILD_TOWER: u64 = 20;
+const CMD_FUSE_TOWERS: u64 = 21;
+const CMD_BURN_TOWER: u64 = 22;
+const CMD_DEPOSIT_TOWER: u64 = 23;
+const CMD_BID_TILE: u64 = 24;
+const CMD_COLLECT_FEES: u64 = 25;
+
+/// Tower ids fit into 32 bits so that commands can pack them with another argument
+pub const MAX_TOWER_ID: u64 = 0xffff_ffff;
+
+/// The player of the operator with the nonce checked, created on the first operator command
+fn operator(pkey: &[u64; 4], nonce: u64) -> TDPlayer {
+    unsafe { require(*pkey == *crate::config::ADMIN_PUBKEY) };
+    let mut admin = TDPlayer::get(pkey).unwrap_or_else(|| TDPlayer::new_from_pid(TDPlayer::pkey_to_pid(pkey)));
+    admin.check_and_inc_nonce(nonce);
+    admin
+}
 
 /// Step function receives a encoded command and changes the global state accordingly
 pub fn handle_command(commands: &[u64; 4], pkey: &[u64; 4]) -> Result<(), u32> {
@@ -39,17 +78,27 @@ pub fn handle_command(commands: &[u64; 4], pkey: &[u64; 4]) -> Result<(), u32> {
     let nonce = commands[0] >> 16;
     match command {
         CMD_RUN => {
-            unsafe { crate::config::GLOBAL.run() };
+            unsafe { event::EVENTS.clear() };
+            let tick = state::advance_tick();
+            let arenas = unsafe { &mut crate::config::ARENAS };
+            for arena in arenas.iter_mut() {
+                arena.run();
+            }
+            delegation::expire_delegations(tick);
+            withdrawal::process_withdrawals(tick);
+            auction::settle_auctions(tick);
             Ok(())
         },
         CMD_PLACE_TOWER => {
             let mut player = TDPlayer::get(pkey).unwrap();
             let objindex = commands[1];
             player.check_and_inc_nonce(nonce);
-            unsafe { require(player.owns(objindex)) };
+            delegation::check_control(&player, pkey, objindex)?;
             let pos = commands[2].to_le_bytes();
             let pos = u16::from_le_bytes(pos[0..2].try_into().unwrap());
-            state::handle_place_tower(&to_full_obj_id(objindex), pos as usize)?;
+            let arena = commands[3];
+            state::handle_place_tower(&to_full_obj_id(objindex), arena, pos as usize)?;
+            player.data.stats.towers_placed += 1;
             player.store();
             Ok(())
         },
@@ -58,7 +107,11 @@ pub fn handle_command(commands: &[u64; 4], pkey: &[u64; 4]) -> Result<(), u32> {
             player.check_and_inc_nonce(nonce);
             let objindex = commands[1];
             unsafe { require(player.owns(objindex)) };
-            state::handle_upgrade_inventory(&to_full_obj_id(objindex));
+            delegation::check_not_delegated(objindex)?;
+            let recipe = commands[2];
+            let lvl = state::handle_upgrade_inventory(&to_full_obj_id(objindex), recipe, None)?;
+            let pid = TDPlayer::pkey_to_pid(pkey);
+            achievement::check_achievements(&mut player, &pid, crate::config::ACHIEVEMENT_TOWER_LEVEL, lvl);
             player.store();
             Ok(())
         },
@@ -67,18 +120,20 @@ pub fn handle_command(commands: &[u64; 4], pkey: &[u64; 4]) -> Result<(), u32> {
             TDPlayer::get_and_check_nonce(&pid, nonce);
             let objindex = commands[1];
             let target_pid = [commands[2], commands[3]]; // 128bit security strength
-            state::handle_update_inventory(&to_full_obj_id(objindex), feature, &target_pid);
-            Ok(())
+            bridge::handle_mint_tower(&to_full_obj_id(objindex), feature, &target_pid)
         },
         CMD_WITHDRAW_TOWER => {
-            let objindex = commands[1];
-            state::handle_withdraw_tower(nonce, &to_full_obj_id(objindex), pkey);
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            bridge::handle_withdraw_tower(&mut player, pkey, &[commands[1], commands[2], commands[3]])?;
+            player.store();
             Ok(())
         },
         CMD_DROP_TOWER => {
             let mut player = TDPlayer::get(pkey).unwrap();
             player.check_and_inc_nonce(nonce);
             let inventory_index = commands[1];
+            delegation::check_control(&player, pkey, inventory_index)?;
             state::handle_drop_tower(&to_full_obj_id(inventory_index));
             player.store();
             Ok(())
@@ -87,20 +142,176 @@ pub fn handle_command(commands: &[u64; 4], pkey: &[u64; 4]) -> Result<(), u32> {
             let mut player = TDPlayer::get(pkey).unwrap();
             player.check_and_inc_nonce(nonce);
             let inventory_index = commands[1];
-            state::handle_collect_rewards(&mut player, &to_full_obj_id(inventory_index));
+            state::handle_collect_rewards(&mut player, pkey, &to_full_obj_id(inventory_index))?;
             player.store();
             Ok(())
         },
         CMD_WITHDRAW_REWARDS => {
             let mut player = TDPlayer::get(pkey).unwrap();
             player.check_and_inc_nonce(nonce);
-            let amount = commands[1] & 0xffffffff;
-            unsafe {require(player.data.reward >= amount)};
-            player.data.reward -= amount;
-            SettlementInfo::append_settlement([commands[1], commands[2], commands[3]]);
+            withdrawal::handle_withdraw_rewards(&mut player, pkey, &[commands[1], commands[2], commands[3]])?;
+            player.store();
+            Ok(())
+        },
+        CMD_TRANSFER_TOWER => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            let objindex = commands[1];
+            unsafe { require(player.owns(objindex)) };
+            delegation::check_not_delegated(objindex)?;
+            let target_pid = [commands[2], commands[3]];
+            state::handle_transfer_tower(&mut player, pkey, &to_full_obj_id(objindex), &target_pid)?;
+            player.store();
+            Ok(())
+        },
+        CMD_LIST_TOWER => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            let objindex = commands[1];
+            unsafe { require(player.owns(objindex)) };
+            delegation::check_not_delegated(objindex)?;
+            let price = commands[2];
+            market::handle_list_tower(&mut player, pkey, &to_full_obj_id(objindex), price)?;
+            player.store();
+            Ok(())
+        },
+        CMD_CANCEL_LISTING => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            market::handle_cancel_listing(&mut player, pkey, commands[1])?;
+            player.store();
+            Ok(())
+        },
+        CMD_BUY_TOWER => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            let objindex = commands[1];
+            market::handle_buy_tower(&mut player, pkey, &to_full_obj_id(objindex))?;
+            player.store();
+            Ok(())
+        },
+        CMD_DELEGATE_TOWER => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            let objindex = commands[1] & MAX_TOWER_ID;
+            let ticks = commands[1] >> 32;
+            unsafe { require(player.owns(objindex)) };
+            let delegate = [commands[2], commands[3]];
+            let expires = state::current_tick() + ticks;
+            delegation::handle_delegate_tower(&mut player, pkey, &to_full_obj_id(objindex), &delegate, feature, expires)?;
+            player.store();
+            Ok(())
+        },
+        CMD_COLLECT_ALL_REWARDS => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            state::handle_collect_all_rewards(&mut player, pkey)?;
+            player.store();
+            Ok(())
+        },
+        CMD_SET_AUTO_COLLECT => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            player.data.auto_collect = feature != 0;
+            player.store();
+            Ok(())
+        },
+        CMD_CREATE_GUILD => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            guild::handle_create_guild(&mut player, pkey, commands[1])?;
             player.store();
             Ok(())
         },
+        CMD_JOIN_GUILD => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            guild::handle_join_guild(&mut player, pkey, commands[1])?;
+            player.store();
+            Ok(())
+        },
+        CMD_DEPOSIT_GUILD => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            guild::handle_deposit_guild(&mut player, commands[1])?;
+            player.store();
+            Ok(())
+        },
+        CMD_PROMOTE_OFFICER => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            guild::handle_promote_officer(&player, pkey, &[commands[1], commands[2]])?;
+            player.store();
+            Ok(())
+        },
+        CMD_DONATE_TOWER => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            let objindex = commands[1];
+            unsafe { require(player.owns(objindex)) };
+            guild::handle_donate_tower(&mut player, &to_full_obj_id(objindex))?;
+            player.store();
+            Ok(())
+        },
+        CMD_UPGRADE_GUILD_TOWER => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            guild::handle_upgrade_guild_tower(&player, pkey, commands[1], commands[2])?;
+            player.store();
+            Ok(())
+        },
+        CMD_FUSE_TOWERS => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            // the third tower is optional
+            let tower_ids: Vec<u64> = commands[1..4]
+                .iter()
+                .enumerate()
+                .filter(|(i, id)| *i < 2 || **id != 0)
+                .map(|(_, id)| *id)
+                .collect();
+            for tower_id in tower_ids.iter() {
+                unsafe { require(player.owns(*tower_id)) };
+                delegation::check_not_delegated(*tower_id)?;
+            }
+            state::handle_fuse_towers(&mut player, &tower_ids)?;
+            player.store();
+            Ok(())
+        },
+        CMD_BURN_TOWER => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            let objindex = commands[1];
+            unsafe { require(player.owns(objindex)) };
+            state::handle_burn_tower(&mut player, &to_full_obj_id(objindex))?;
+            player.store();
+            Ok(())
+        },
+        CMD_DEPOSIT_TOWER => {
+            let admin = operator(pkey, nonce);
+            let objindex = commands[1];
+            let target_pid = [commands[2], commands[3]];
+            bridge::handle_deposit_tower(&to_full_obj_id(objindex), &target_pid)?;
+            admin.store();
+            Ok(())
+        },
+        CMD_BID_TILE => {
+            let mut player = TDPlayer::get(pkey).unwrap();
+            player.check_and_inc_nonce(nonce);
+            let arena = commands[1];
+            let tile = commands[2];
+            let amount = commands[3];
+            auction::handle_bid_tile(&mut player, pkey, arena, tile, amount)?;
+            player.store();
+            Ok(())
+        },
+        CMD_COLLECT_FEES => {
+            let mut admin = operator(pkey, nonce);
+            market::handle_collect_fees(&mut admin);
+            auction::handle_collect_proceeds(&mut admin);
+            admin.store();
+            Ok(())
+        },
         _ => {
             Ok(())
         }
@@ -112,22 +323,64 @@ pub struct State {}
 #[derive(Serialize)]
 pub struct UserState<'a> {
     player: Option<TDPlayer>,
-    global: &'a crate::game::state::State,
+    global: Option<&'a crate::game::state::State<crate::config::GameCoordinate>>,
+    events: &'a Vec<event::Event>,
+    guild: Option<guild::Guild>,
+    inventory_size: u64, // the player only carries one page of the inventory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    withdrawals: Option<withdrawal::Withdrawals>, // the queue, only for the operator
 }
 
 impl State {
+    /// pid is the pubkey of the player optionally followed by an arena id and an inventory page
     pub fn get_state(pid: Vec<u64>) -> String {
         //zkwasm_rust_sdk::dbg!("finish loading {:?}", merkle_root);
-        let global = unsafe { &crate::config::GLOBAL };
-        let player = TDPlayer::get(&pid.try_into().unwrap());
+        let arenas = unsafe { &crate::config::ARENAS };
+        let arena = pid.get(4).map_or(0, |x| *x as usize);
+        let page = pid.get(5).map_or(0, |x| *x as usize);
+        let mut player = TDPlayer::get(&pid[0..4].try_into().unwrap());
+        let inventory_size = player.as_ref().map_or(0, |p| p.data.inventory.len() as u64);
+        if let Some(p) = player.as_mut() {
+            p.data.inventory = p.data.inventory_page(page);
+        }
+        let guild = player
+            .as_ref()
+            .filter(|p| p.data.guild != 0)
+            .and_then(|p| guild::Guild::get(p.data.guild));
+        let withdrawals = Some(&pid[0..4])
+            .filter(|pkey| *pkey == *crate::config::ADMIN_PUBKEY)
+            .map(|_| withdrawal::Withdrawals::get());
         serde_json::to_string(
             &(UserState {
                 player,
-                global: &global,
+                global: arenas.get(arena),
+                events: unsafe { &event::EVENTS },
+                guild,
+                inventory_size,
+                withdrawals,
             }),
         )
         .unwrap()
     }
+    pub fn get_market() -> String {
+        serde_json::to_string(&market::Market::get()).unwrap()
+    }
+    pub fn get_delegations() -> String {
+        serde_json::to_string(&delegation::Delegations::get()).unwrap()
+    }
+    pub fn get_withdrawals() -> String {
+        serde_json::to_string(&withdrawal::Withdrawals::get().summary()).unwrap()
+    }
+    pub fn get_leaderboard() -> String {
+        serde_json::to_string(&leaderboard::Leaderboard::get()).unwrap()
+    }
+    pub fn get_tower_metadata(tower_id: u64) -> String {
+        let inventory_obj = state::load_inventory_object(&to_full_obj_id(tower_id));
+        serde_json::to_string(&inventory_obj.map(|o| metadata::TowerMetadata::new(&o))).unwrap()
+    }
+    pub fn get_auctions() -> String {
+        serde_json::to_string(&auction::Auctions::get()).unwrap()
+    }
     pub fn initialize() {
         init_state()
     }
@@ -138,6 +391,46 @@ pub struct Transaction {
 }
 
 pub const ERROR_POSITION_OCCUPIED:u32 = 1;
+pub const ERROR_POSITION_ON_PATH:u32 = 2;
+pub const ERROR_POSITION_BLOCKED:u32 = 3;
+pub const ERROR_POSITION_RESERVED:u32 = 4;
+pub const ERROR_ARENA_NOT_FOUND:u32 = 5;
+pub const ERROR_TOWER_ALREADY_PLACED:u32 = 6;
+pub const ERROR_UNKNOWN_RECIPE:u32 = 7;
+pub const ERROR_RECIPE_NOT_APPLICABLE:u32 = 8;
+pub const ERROR_NOT_ENOUGH_REWARD:u32 = 9;
+pub const ERROR_MAX_LEVEL:u32 = 10;
+pub const ERROR_INVALID_RECIPIENT:u32 = 11;
+pub const ERROR_LISTING_NOT_FOUND:u32 = 12;
+pub const ERROR_NOT_SELLER:u32 = 13;
+pub const ERROR_TOWER_DELEGATED:u32 = 14;
+pub const ERROR_INVALID_AMOUNT:u32 = 15;
+pub const ERROR_INVALID_ADDRESS:u32 = 16;
+pub const ERROR_WITHDRAW_LIMIT:u32 = 17;
+pub const ERROR_GUILD_NOT_FOUND:u32 = 18;
+pub const ERROR_GUILD_EXISTS:u32 = 19;
+pub const ERROR_ALREADY_IN_GUILD:u32 = 20;
+pub const ERROR_NOT_IN_GUILD:u32 = 21;
+pub const ERROR_NOT_GUILD_OFFICER:u32 = 22;
+pub const ERROR_FUSION_MISMATCH:u32 = 23;
+pub const ERROR_TOWER_NOT_LOCKED:u32 = 24;
+pub const ERROR_INVENTORY_FULL:u32 = 25;
+pub const ERROR_NOT_PREMIUM_TILE:u32 = 26;
+pub const ERROR_BID_TOO_LOW:u32 = 27;
+pub const ERROR_TILE_LEASED:u32 = 28;
+pub const ERROR_POSITION_PREMIUM:u32 = 29;
+pub const ERROR_TOWER_LIMIT:u32 = 30;
+pub const ERROR_TOWERS_TOO_CLOSE:u32 = 31;
+pub const ERROR_INVALID_TOWER_ID:u32 = 32;
+pub const ERROR_INVALID_SHARE:u32 = 33;
+pub const ERROR_INVALID_GUILD_ID:u32 = 34;
+pub const ERROR_NOT_GUILD_TOWER:u32 = 35;
+pub const ERROR_TOWER_LOCKED:u32 = 36;
+pub const ERROR_TOWER_EXISTS:u32 = 37;
+pub const ERROR_MARKET_FULL:u32 = 38;
+pub const ERROR_DELEGATION_LIMIT:u32 = 39;
+pub const ERROR_GUILD_TOWER_ON_PREMIUM:u32 = 40;
+pub const ERROR_AUCTION_LIMIT:u32 = 41;
 
 impl Transaction {
     pub fn decode(params: [u64; 4]) -> Self {
@@ -147,6 +440,46 @@ impl Transaction {
     pub fn decode_error(e: u32) -> &'static str {
         match e {
             ERROR_POSITION_OCCUPIED => "PositionOccupied",
+            ERROR_POSITION_ON_PATH => "PositionOnPath",
+            ERROR_POSITION_BLOCKED => "PositionBlocked",
+            ERROR_POSITION_RESERVED => "PositionReserved",
+            ERROR_ARENA_NOT_FOUND => "ArenaNotFound",
+            ERROR_TOWER_ALREADY_PLACED => "TowerAlreadyPlaced",
+            ERROR_UNKNOWN_RECIPE => "UnknownRecipe",
+            ERROR_RECIPE_NOT_APPLICABLE => "RecipeNotApplicable",
+            ERROR_NOT_ENOUGH_REWARD => "NotEnoughReward",
+            ERROR_MAX_LEVEL => "MaxLevel",
+            ERROR_INVALID_RECIPIENT => "InvalidRecipient",
+            ERROR_LISTING_NOT_FOUND => "ListingNotFound",
+            ERROR_NOT_SELLER => "NotSeller",
+            ERROR_TOWER_DELEGATED => "TowerDelegated",
+            ERROR_INVALID_AMOUNT => "InvalidAmount",
+            ERROR_INVALID_ADDRESS => "InvalidAddress",
+            ERROR_WITHDRAW_LIMIT => "WithdrawLimit",
+            ERROR_GUILD_NOT_FOUND => "GuildNotFound",
+            ERROR_GUILD_EXISTS => "GuildExists",
+            ERROR_ALREADY_IN_GUILD => "AlreadyInGuild",
+            ERROR_NOT_IN_GUILD => "NotInGuild",
+            ERROR_NOT_GUILD_OFFICER => "NotGuildOfficer",
+            ERROR_FUSION_MISMATCH => "FusionMismatch",
+            ERROR_TOWER_NOT_LOCKED => "TowerNotLocked",
+            ERROR_INVENTORY_FULL => "InventoryFull",
+            ERROR_NOT_PREMIUM_TILE => "NotPremiumTile",
+            ERROR_BID_TOO_LOW => "BidTooLow",
+            ERROR_TILE_LEASED => "TileLeased",
+            ERROR_POSITION_PREMIUM => "PositionPremium",
+            ERROR_TOWER_LIMIT => "TowerLimit",
+            ERROR_TOWERS_TOO_CLOSE => "TowersTooClose",
+            ERROR_INVALID_TOWER_ID => "InvalidTowerId",
+            ERROR_INVALID_SHARE => "InvalidShare",
+            ERROR_INVALID_GUILD_ID => "InvalidGuildId",
+            ERROR_NOT_GUILD_TOWER => "NotGuildTower",
+            ERROR_TOWER_LOCKED => "TowerLocked",
+            ERROR_TOWER_EXISTS => "TowerExists",
+            ERROR_MARKET_FULL => "MarketFull",
+            ERROR_DELEGATION_LIMIT => "DelegationLimit",
+            ERROR_GUILD_TOWER_ON_PREMIUM => "GuildTowerOnPremium",
+            ERROR_AUCTION_LIMIT => "AuctionLimit",
             _ => "UnknownError"
 
         }
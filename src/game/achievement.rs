@@ -0,0 +1,51 @@
+use super::event::emit;
+use super::event::Event;
+use crate::config::CONFIG;
+use crate::player::TDPlayer;
+
+/// Unlocks every configured achievement of the given kind whose threshold `value` reaches,
+/// each achievement is unlocked once and grants its bonus to the player's reward
+pub fn check_achievements(player: &mut TDPlayer, pid: &[u64; 2], kind: u64, value: u64) {
+    for (id, a) in CONFIG.achievements.iter().enumerate() {
+        if a.kind == kind && value >= a.threshold && player.data.unlock(id as u64) {
+            player.data.reward += a.bonus;
+            emit(Event::Achievement(
+                [pid[0].to_string(), pid[1].to_string()],
+                id as u64,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_achievements;
+    use crate::config::ACHIEVEMENT_KILLS;
+    use crate::config::ACHIEVEMENT_TOWER_LEVEL;
+    use crate::config::CONFIG;
+    use crate::game::event::Event;
+    use crate::game::event::EVENTS;
+    use crate::game::testing;
+
+    #[test]
+    fn test_unlock_once_with_bonus() {
+        let _guard = testing::setup();
+        let pid = [1, 2];
+        let mut player = testing::new_player(pid, 0);
+        let (id, a) = CONFIG.achievements.iter().enumerate().find(|(_, a)| a.kind == ACHIEVEMENT_KILLS).unwrap();
+        check_achievements(&mut player, &pid, ACHIEVEMENT_KILLS, a.threshold - 1);
+        assert_eq!(player.data.achievements, 0);
+        check_achievements(&mut player, &pid, ACHIEVEMENT_KILLS, a.threshold);
+        assert_eq!(player.data.achievements, 1 << id);
+        assert_eq!(player.data.reward, a.bonus);
+        check_achievements(&mut player, &pid, ACHIEVEMENT_KILLS, a.threshold + 5);
+        assert_eq!(player.data.reward, a.bonus);
+
+        let events = unsafe { &EVENTS };
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], Event::Achievement(_, unlocked) if *unlocked == id as u64));
+        // another kind is not affected by the value
+        check_achievements(&mut player, &pid, ACHIEVEMENT_TOWER_LEVEL, 0);
+        assert_eq!(player.data.achievements, 1 << id);
+    }
+}
@@ -0,0 +1,288 @@
+use super::event::emit;
+use super::event::Event;
+use super::market::pid_serializer;
+use super::state::current_tick;
+use super::ERROR_ARENA_NOT_FOUND;
+use super::ERROR_AUCTION_LIMIT;
+use super::ERROR_BID_TOO_LOW;
+use super::ERROR_NOT_ENOUGH_REWARD;
+use super::ERROR_NOT_PREMIUM_TILE;
+use super::ERROR_POSITION_PREMIUM;
+use super::ERROR_TILE_LEASED;
+use crate::config::CONFIG;
+use crate::player::TDPlayer;
+use crate::tile::coordinate::TileType;
+use crate::MERKLE_MAP;
+use serde::Serialize;
+
+/// Auctions and leases of all premium tiles live in one object so that they can be settled in one pass
+const AUCTIONS_KEY: [u64; 4] = [0, 0xffff, 0xff01, 0xff0a];
+
+#[derive(Clone, Serialize)]
+pub struct TileAuction {
+    pub arena: u64,
+    pub tile: u64,
+    #[serde(serialize_with = "pid_serializer")]
+    pub bidder: [u64; 2],
+    pub bid: u64,
+    pub closes: u64, // tick at which the running auction closes, 0 if there is none
+    #[serde(serialize_with = "pid_serializer")]
+    pub holder: [u64; 2],
+    pub lease_end: u64, // tick at which the placement rights of the holder end, 0 if there is none
+}
+
+#[derive(Clone, Serialize)]
+pub struct Auctions {
+    pub tiles: Vec<TileAuction>,
+    pub proceeds: u64, // winning bids collected so far
+}
+
+impl Auctions {
+    pub fn get() -> Self {
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        let data = kvpair.get(&AUCTIONS_KEY);
+        let mut u64data = data.iter();
+        let mut next = || u64data.next().map_or(0, |x| *x);
+        let proceeds = next();
+        let len = next();
+        let tiles = (0..len)
+            .map(|_| TileAuction {
+                arena: next(),
+                tile: next(),
+                bidder: [next(), next()],
+                bid: next(),
+                closes: next(),
+                holder: [next(), next()],
+                lease_end: next(),
+            })
+            .collect();
+        Auctions { tiles, proceeds }
+    }
+    pub fn store(&self) {
+        let mut data = vec![self.proceeds, self.tiles.len() as u64];
+        for a in self.tiles.iter() {
+            data.extend_from_slice(&[
+                a.arena,
+                a.tile,
+                a.bidder[0],
+                a.bidder[1],
+                a.bid,
+                a.closes,
+                a.holder[0],
+                a.holder[1],
+                a.lease_end,
+            ]);
+        }
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        kvpair.set(&AUCTIONS_KEY, data.as_slice());
+    }
+    fn position(&self, arena: u64, tile: u64) -> Option<usize> {
+        self.tiles.iter().position(|a| a.arena == arena && a.tile == tile)
+    }
+}
+
+/// Only the holder of the lease may place a tower on a premium tile
+pub fn check_placement_rights(arena: u64, tile: u64, owner: &[u64; 2]) -> Result<(), u32> {
+    let auctions = Auctions::get();
+    match auctions.position(arena, tile) {
+        Some(index) if auctions.tiles[index].lease_end != 0 && auctions.tiles[index].holder == *owner => Ok(()),
+        _ => Err(ERROR_POSITION_PREMIUM),
+    }
+}
+
+/// Bids on a premium tile that is not leased, the first bid opens an auction that runs
+/// for `auction_ticks`. The bid is paid from the reward and the previous bidder is refunded.
+pub fn handle_bid_tile(player: &mut TDPlayer, pkey: &[u64; 4], arena: u64, tile: u64, amount: u64) -> Result<(), u32> {
+    let arenas = unsafe { &crate::config::ARENAS };
+    let global = arenas.get(arena as usize).ok_or(ERROR_ARENA_NOT_FOUND)?;
+    match global.map.tiles.get(tile as usize) {
+        Some(t) if t.tile_type == TileType::Premium => (),
+        _ => return Err(ERROR_NOT_PREMIUM_TILE),
+    }
+    let mut auctions = Auctions::get();
+    let index = match auctions.position(arena, tile) {
+        Some(index) => index,
+        None => {
+            if auctions.tiles.len() as u64 >= CONFIG.max_auctions {
+                return Err(ERROR_AUCTION_LIMIT);
+            }
+            auctions.tiles.push(TileAuction {
+                arena,
+                tile,
+                bidder: [0, 0],
+                bid: 0,
+                closes: 0,
+                holder: [0, 0],
+                lease_end: 0,
+            });
+            auctions.tiles.len() - 1
+        }
+    };
+    let auction = &mut auctions.tiles[index];
+    if auction.lease_end != 0 {
+        return Err(ERROR_TILE_LEASED);
+    }
+    if amount <= auction.bid {
+        return Err(ERROR_BID_TOO_LOW);
+    }
+    let pid = TDPlayer::pkey_to_pid(pkey);
+    // raising an own bid only pays the difference
+    let refund = if auction.bidder == pid { auction.bid } else { 0 };
+    if player.data.reward + refund < amount {
+        return Err(ERROR_NOT_ENOUGH_REWARD);
+    }
+    player.data.reward = player.data.reward + refund - amount;
+    if auction.bid > 0 && auction.bidder != pid {
+        let mut outbid = TDPlayer::get_from_pid(&auction.bidder).unwrap();
+        outbid.data.reward += auction.bid;
+        outbid.store();
+    }
+    if auction.closes == 0 {
+        auction.closes = current_tick() + CONFIG.auction_ticks;
+    }
+    auction.bidder = pid;
+    auction.bid = amount;
+    auctions.store();
+    Ok(())
+}
+
+/// Credits the winning bids collected so far to the reward of the operator
+pub fn handle_collect_proceeds(admin: &mut TDPlayer) {
+    let mut auctions = Auctions::get();
+    admin.data.reward += auctions.proceeds;
+    auctions.proceeds = 0;
+    auctions.store();
+}
+
+/// Hands closed auctions to the highest bidder for `lease_ticks` and clears expired leases,
+/// a tower left on a tile whose lease expired is removed from the board
+pub fn settle_auctions(tick: u64) {
+    let mut auctions = Auctions::get();
+    if auctions.tiles.is_empty() {
+        return;
+    }
+    let arenas = unsafe { &mut crate::config::ARENAS };
+    let mut proceeds = 0;
+    auctions.tiles.retain_mut(|a| {
+        if a.closes != 0 && a.closes <= tick {
+            proceeds += a.bid;
+            a.holder = a.bidder;
+            a.lease_end = tick + CONFIG.lease_ticks;
+            a.bidder = [0, 0];
+            a.bid = 0;
+            a.closes = 0;
+            true
+        } else if a.lease_end != 0 && a.lease_end <= tick {
+            let global = &mut arenas[a.arena as usize];
+            let position = global.map.coordinate_of_tile_index(a.tile as usize);
+            if let Some(index) = global.towers.iter().position(|t| t.position == position) {
+                let tower = global.remove_tower_at(index);
+                let owner = tower.object.object.get_the_tower().owner;
+                emit(Event::TowerEvicted(
+                    [owner[0].to_string(), owner[1].to_string()],
+                    tower.object.object_id[0].to_string(),
+                ));
+            }
+            false
+        } else {
+            true
+        }
+    });
+    auctions.proceeds += proceeds;
+    auctions.store();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ARENAS;
+    use crate::game::event::EVENTS;
+    use crate::game::object::to_full_obj_id;
+    use crate::game::state::find_placed_tower;
+    use crate::game::state::handle_place_tower;
+    use crate::game::testing;
+
+    /// The first arena with a premium tile and that tile
+    fn premium_tile() -> (u64, u64) {
+        let arenas = unsafe { &ARENAS };
+        (0..arenas.len())
+            .find_map(|arena| {
+                let tiles = testing::tiles_of_type(arena, TileType::Premium);
+                tiles.first().map(|tile| (arena as u64, *tile as u64))
+            })
+            .unwrap()
+    }
+
+    fn reward(pid: [u64; 2]) -> u64 {
+        TDPlayer::get_from_pid(&pid).unwrap().data.reward
+    }
+
+    #[test]
+    fn test_bid_tile() {
+        let _guard = testing::setup();
+        let (a, b) = (([0, 0x60, 1, 0], [0x60, 1]), ([0, 0x60, 2, 0], [0x60, 2]));
+        testing::new_player(a.1, 100);
+        testing::new_player(b.1, 100);
+        let (arena, tile) = premium_tile();
+        let bid = |player: ([u64; 4], [u64; 2]), tile, amount| {
+            let mut p = TDPlayer::get_from_pid(&player.1).unwrap();
+            handle_bid_tile(&mut p, &player.0, arena, tile, amount).map(|_| p.store())
+        };
+        let buildable = testing::buildable_tile(arena as usize) as u64;
+        assert_eq!(bid(a, buildable, 10), Err(ERROR_NOT_PREMIUM_TILE));
+        assert_eq!(bid(a, tile, 101), Err(ERROR_NOT_ENOUGH_REWARD));
+        bid(a, tile, 10).unwrap();
+        assert_eq!(bid(b, tile, 10), Err(ERROR_BID_TOO_LOW));
+        // the outbid player gets the bid back, raising an own bid only pays the difference
+        bid(b, tile, 15).unwrap();
+        bid(b, tile, 20).unwrap();
+        assert_eq!((reward(a.1), reward(b.1)), (100, 80));
+        assert_eq!(Auctions::get().tiles[0].closes, CONFIG.auction_ticks);
+    }
+
+    #[test]
+    fn test_auction_limit() {
+        let _guard = testing::setup();
+        let (pkey, pid) = ([0, 0x62, 1, 0], [0x62, 1]);
+        let mut player = testing::new_player(pid, 100);
+        let (arena, tile) = premium_tile();
+        let auction = TileAuction { arena, tile, bidder: [0, 0], bid: 0, closes: 0, holder: [0, 0], lease_end: 0 };
+        let mut auctions = Auctions::get();
+        auctions.tiles = vec![auction; CONFIG.max_auctions as usize];
+        auctions.tiles.iter_mut().skip(1).for_each(|a| a.arena = u64::MAX);
+        auctions.store();
+        // bids on running auctions are taken, new auctions are not opened
+        handle_bid_tile(&mut player, &pkey, arena, tile, 10).unwrap();
+        auctions.tiles[0].tile = u64::MAX;
+        auctions.store();
+        assert_eq!(handle_bid_tile(&mut player, &pkey, arena, tile, 10), Err(ERROR_AUCTION_LIMIT));
+    }
+
+    #[test]
+    fn test_lease_premium_tile() {
+        let _guard = testing::setup();
+        let (pkey, pid) = ([0, 0x61, 2, 0], [0x61, 2]);
+        let mut holder = testing::new_player(pid, 100);
+        testing::mint(1, [0x61, 1]);
+        testing::mint(2, pid);
+        let (arena, tile) = premium_tile();
+        handle_bid_tile(&mut holder, &pkey, arena, tile, 20).unwrap();
+        assert_eq!(handle_place_tower(&to_full_obj_id(2), arena, tile as usize), Err(ERROR_POSITION_PREMIUM));
+
+        settle_auctions(CONFIG.auction_ticks);
+        assert_eq!(Auctions::get().proceeds, 20);
+        assert_eq!(handle_bid_tile(&mut holder, &pkey, arena, tile, 50), Err(ERROR_TILE_LEASED));
+        assert_eq!(handle_place_tower(&to_full_obj_id(1), arena, tile as usize), Err(ERROR_POSITION_PREMIUM));
+        handle_place_tower(&to_full_obj_id(2), arena, tile as usize).unwrap();
+
+        // the tower leaves the board with the lease
+        settle_auctions(CONFIG.auction_ticks + CONFIG.lease_ticks);
+        assert!(find_placed_tower(&to_full_obj_id(2)).is_none());
+        assert!(Auctions::get().tiles.is_empty());
+        assert!(matches!(unsafe { &EVENTS[..] }, [Event::TowerEvicted(_, id)] if id == "2"));
+
+        let mut admin = testing::new_player([0x37, 0x38], 0);
+        handle_collect_proceeds(&mut admin);
+        assert_eq!((admin.data.reward, Auctions::get().proceeds), (20, 0));
+    }
+}
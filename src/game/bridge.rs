@@ -0,0 +1,177 @@
+use super::delegation::check_not_delegated;
+use super::leaderboard::Leaderboard;
+use super::metadata::TowerMetadata;
+use super::state::find_placed_tower;
+use super::state::handle_update_inventory;
+use super::ERROR_INVALID_ADDRESS;
+use super::ERROR_INVALID_TOWER_ID;
+use super::ERROR_TOWER_EXISTS;
+use super::ERROR_TOWER_LOCKED;
+use super::MAX_TOWER_ID;
+use super::ERROR_TOWER_ALREADY_PLACED;
+use super::ERROR_TOWER_NOT_LOCKED;
+use crate::config::GameDirection;
+use crate::game::object::InventoryObject;
+use crate::player::Owner;
+use crate::player::TDPlayer;
+use crate::settlement::SettlementInfo;
+use crate::tx::TowerWithdrawInfo;
+use zkwasm_rust_sdk::require;
+
+/// Mints a new tower into the inventory of the given player. Ids of locked towers
+/// and of towers that exist already are taken, ids above MAX_TOWER_ID do not fit
+/// into the NFT withdrawal
+pub fn handle_mint_tower(iid: &[u64; 4], feature: u64, pid: &[u64; 2]) -> Result<(), u32> {
+    if iid[0] > MAX_TOWER_ID {
+        return Err(ERROR_INVALID_TOWER_ID);
+    }
+    if let Some(obj) = InventoryObject::<GameDirection>::get(iid) {
+        return Err(if obj.locked { ERROR_TOWER_LOCKED } else { ERROR_TOWER_EXISTS });
+    }
+    handle_update_inventory(iid, feature, pid)
+}
+
+/// Locks an unplaced tower and emits an NFT withdrawal carrying its metadata hash,
+/// the pending tower reward goes to the player. The locked tower has no owner on L2
+/// and leaves the leaderboard.
+pub fn handle_withdraw_tower(player: &mut TDPlayer, pkey: &[u64; 4], limbs: &[u64; 3]) -> Result<(), u32> {
+    let info = TowerWithdrawInfo::decode(limbs, [0; 32]);
+    let iid = crate::game::object::to_full_obj_id(info.tower_id);
+    unsafe { require(player.owns(info.tower_id)) };
+    check_not_delegated(info.tower_id)?;
+    if !info.is_valid() {
+        return Err(ERROR_INVALID_ADDRESS);
+    }
+    if find_placed_tower(&iid).is_some() {
+        return Err(ERROR_TOWER_ALREADY_PLACED);
+    }
+    let mut inventory_obj = InventoryObject::<GameDirection>::get(&iid).unwrap();
+    let tower = inventory_obj.object.get_the_tower_mut();
+    unsafe {
+        require(tower.owner == TDPlayer::pkey_to_pid(pkey));
+    }
+    tower.owner = [0, 0];
+    player.data.reward += inventory_obj.reward;
+    inventory_obj.reward = 0;
+    inventory_obj.locked = true;
+    inventory_obj.store();
+    player.data.remove_tower(info.tower_id);
+    let mut leaderboard = Leaderboard::get();
+    leaderboard.remove_tower(info.tower_id);
+    leaderboard.store();
+    let metadata_hash = TowerMetadata::new(&inventory_obj).hash();
+    SettlementInfo::append_tower_settlement(TowerWithdrawInfo { metadata_hash, ..info });
+    Ok(())
+}
+
+/// Unlocks a tower deposited from L1 into the inventory of the given player
+pub fn handle_deposit_tower(iid: &[u64; 4], pid: &[u64; 2]) -> Result<(), u32> {
+    match InventoryObject::<GameDirection>::get(iid) {
+        Some(obj) if obj.locked => (),
+        _ => return Err(ERROR_TOWER_NOT_LOCKED),
+    }
+    handle_update_inventory(iid, 0, pid)?;
+    let mut inventory_obj = InventoryObject::<GameDirection>::get(iid).unwrap();
+    inventory_obj.locked = false;
+    inventory_obj.store();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::delegation::Delegation;
+    use crate::game::delegation::Delegations;
+    use crate::game::object::to_full_obj_id;
+    use crate::game::state::handle_place_tower;
+    use crate::game::testing;
+    use crate::game::ERROR_TOWER_DELEGATED;
+    use crate::MERKLE_MAP;
+
+    fn is_locked(tower_id: u64) -> bool {
+        InventoryObject::<GameDirection>::get(&to_full_obj_id(tower_id)).is_some_and(|x| x.locked)
+    }
+
+    // tower id in the low 32 bits, a non zero address in the rest
+    fn limbs(tower_id: u64) -> [u64; 3] {
+        [tower_id | 0xaa << 32, 0xbb, 0xcc]
+    }
+
+    #[test]
+    fn test_mint_tower() {
+        let _guard = testing::setup();
+        let pid = [1, 2];
+        let iid = to_full_obj_id(MAX_TOWER_ID + 1);
+        assert_eq!(handle_mint_tower(&iid, 0, &pid), Err(ERROR_INVALID_TOWER_ID));
+        testing::mint(1, pid);
+        assert_eq!(handle_mint_tower(&to_full_obj_id(1), 0, &[3, 4]), Err(ERROR_TOWER_EXISTS));
+        testing::mint(2, pid);
+        let mut obj = InventoryObject::<GameDirection>::get(&to_full_obj_id(2)).unwrap();
+        obj.locked = true;
+        obj.store();
+        assert_eq!(handle_mint_tower(&to_full_obj_id(2), 0, &pid), Err(ERROR_TOWER_LOCKED));
+        let iid = to_full_obj_id(MAX_TOWER_ID);
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        kvpair.set(&iid, &[]);
+        handle_mint_tower(&iid, 0, &pid).unwrap();
+        let player = TDPlayer::get_from_pid(&pid).unwrap();
+        assert!(player.owns(1) && player.owns(MAX_TOWER_ID));
+    }
+
+    #[test]
+    fn test_withdraw_refuses_placed_and_delegated_towers() {
+        let _guard = testing::setup();
+        let (pkey, pid) = ([0, 1, 2, 0], [1, 2]);
+        testing::new_player(pid, 0);
+        testing::mint(1, pid);
+        testing::mint(2, pid);
+        handle_place_tower(&to_full_obj_id(1), 0, testing::buildable_tile(0)).unwrap();
+        let mut delegations = Delegations::get();
+        delegations.0.push(Delegation { tower_id: 2, owner: pid, delegate: [3, 4], share: 50, expires: 100 });
+        delegations.store();
+        let mut player = TDPlayer::get_from_pid(&pid).unwrap();
+        assert_eq!(handle_withdraw_tower(&mut player, &pkey, &limbs(1)), Err(ERROR_TOWER_ALREADY_PLACED));
+        assert_eq!(handle_withdraw_tower(&mut player, &pkey, &limbs(2)), Err(ERROR_TOWER_DELEGATED));
+        assert_eq!(handle_withdraw_tower(&mut player, &pkey, &[2, 0, 0]), Err(ERROR_TOWER_DELEGATED));
+        assert!(!is_locked(1) && !is_locked(2));
+        assert!(SettlementInfo::flush_settlement().is_empty());
+    }
+
+    #[test]
+    fn test_withdraw_and_deposit_tower() {
+        let _guard = testing::setup();
+        let (pkey, pid, to) = ([0, 1, 2, 0], [1, 2], [0x35, 0x46]);
+        testing::new_player(pid, 0);
+        testing::mint(1, pid);
+        let iid = to_full_obj_id(1);
+        let mut obj = InventoryObject::<GameDirection>::get(&iid).unwrap();
+        obj.reward = 25;
+        obj.store();
+        let mut leaderboard = Leaderboard::get();
+        leaderboard.update_tower(1, 3);
+        leaderboard.store();
+        assert_eq!(handle_deposit_tower(&iid, &to), Err(ERROR_TOWER_NOT_LOCKED));
+
+        let mut player = TDPlayer::get_from_pid(&pid).unwrap();
+        assert_eq!(handle_withdraw_tower(&mut player, &pkey, &[1, 0, 0]), Err(ERROR_INVALID_ADDRESS));
+        handle_withdraw_tower(&mut player, &pkey, &limbs(1)).unwrap();
+        assert!(!player.owns(1));
+        assert_eq!(player.data.reward, 25);
+        assert!(is_locked(1));
+        // one 96 byte tower record with the id after the opcode
+        let bytes = SettlementInfo::flush_settlement();
+        assert_eq!((bytes.len(), bytes[0], bytes[8]), (96, 2, 1));
+        // the metadata of the locked tower matches the hash sent to L1 and has no L2 owner
+        let metadata = TowerMetadata::new(&InventoryObject::<GameDirection>::get(&iid).unwrap());
+        assert_eq!(metadata.owner, [0, 0]);
+        assert_eq!(bytes[64..], metadata.hash());
+        assert!(Leaderboard::get().towers.is_empty());
+
+        handle_deposit_tower(&iid, &to).unwrap();
+        assert!(!is_locked(1));
+        assert!(TDPlayer::get_from_pid(&to).unwrap().owns(1));
+        let obj = InventoryObject::<GameDirection>::get(&iid).unwrap();
+        assert_eq!(obj.object.get_the_tower().owner, to);
+        assert_eq!(handle_deposit_tower(&iid, &to), Err(ERROR_TOWER_NOT_LOCKED));
+    }
+}
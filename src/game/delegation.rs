@@ -0,0 +1,312 @@
+use super::market::pid_serializer;
+use super::state::load_inventory_object;
+use super::state::store_inventory_object;
+use super::state::find_placed_tower;
+use super::bigint_serializer;
+use super::ERROR_DELEGATION_LIMIT;
+use super::ERROR_INVALID_RECIPIENT;
+use super::ERROR_INVALID_SHARE;
+use super::ERROR_TOWER_ALREADY_PLACED;
+use super::ERROR_TOWER_DELEGATED;
+use super::guild::check_guild_control;
+use super::guild::is_guild_tower;
+use crate::config::CONFIG;
+use crate::player::Owner;
+use crate::player::TDPlayer;
+use crate::MERKLE_MAP;
+use serde::Serialize;
+use zkwasm_rust_sdk::require;
+
+/// All active delegations live in one object so that they can be expired in one pass
+const DELEGATIONS_KEY: [u64; 4] = [0, 0xffff, 0xff01, 0xff05];
+
+#[derive(Clone, Serialize)]
+pub struct Delegation {
+    #[serde(serialize_with = "bigint_serializer")]
+    pub tower_id: u64,
+    #[serde(serialize_with = "pid_serializer")]
+    pub owner: [u64; 2],
+    #[serde(serialize_with = "pid_serializer")]
+    pub delegate: [u64; 2],
+    pub share: u64, // percentage of the tower reward that goes to the delegate
+    pub expires: u64, // tick at which control returns to the owner
+}
+
+impl Delegation {
+    /// Splits a tower reward into (owner part, delegate part)
+    pub fn split(&self, reward: u64) -> (u64, u64) {
+        let delegate_part = (reward as u128 * self.share as u128 / 100) as u64;
+        (reward - delegate_part, delegate_part)
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct Delegations(pub Vec<Delegation>);
+
+impl Delegations {
+    pub fn get() -> Self {
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        let data = kvpair.get(&DELEGATIONS_KEY);
+        let mut u64data = data.iter();
+        let len = u64data.next().map_or(0, |x| *x);
+        let delegations = (0..len)
+            .map(|_| Delegation {
+                tower_id: *u64data.next().unwrap(),
+                owner: [*u64data.next().unwrap(), *u64data.next().unwrap()],
+                delegate: [*u64data.next().unwrap(), *u64data.next().unwrap()],
+                share: *u64data.next().unwrap(),
+                expires: *u64data.next().unwrap(),
+            })
+            .collect();
+        Delegations(delegations)
+    }
+    pub fn store(&self) {
+        let mut data = vec![self.0.len() as u64];
+        for d in self.0.iter() {
+            data.extend_from_slice(&[
+                d.tower_id,
+                d.owner[0],
+                d.owner[1],
+                d.delegate[0],
+                d.delegate[1],
+                d.share,
+                d.expires,
+            ]);
+        }
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        kvpair.set(&DELEGATIONS_KEY, data.as_slice());
+    }
+}
+
+pub fn find_delegation(tower_id: u64) -> Option<Delegation> {
+    Delegations::get().0.into_iter().find(|d| d.tower_id == tower_id)
+}
+
+/// Fails if the tower is delegated, used by commands only the owner may issue
+pub fn check_not_delegated(tower_id: u64) -> Result<(), u32> {
+    match find_delegation(tower_id) {
+        Some(_) => Err(ERROR_TOWER_DELEGATED),
+        None => Ok(()),
+    }
+}
+
+/// The delegate controls a delegated tower, the officers of a guild control its towers
+/// and the owner controls it otherwise
+pub fn check_control(player: &TDPlayer, pkey: &[u64; 4], tower_id: u64) -> Result<(), u32> {
+    match find_delegation(tower_id) {
+        Some(d) if d.delegate == TDPlayer::pkey_to_pid(pkey) => Ok(()),
+        Some(_) => Err(ERROR_TOWER_DELEGATED),
+        None if !player.owns(tower_id) && is_guild_tower(player, tower_id) => {
+            check_guild_control(player, pkey, tower_id)
+        }
+        None => {
+            unsafe { require(player.owns(tower_id)) };
+            Ok(())
+        }
+    }
+}
+
+pub fn credit_reward(pid: &[u64; 2], amount: u64) {
+    let mut player = TDPlayer::get_from_pid(pid).unwrap();
+    player.data.reward += amount;
+    player.store();
+}
+
+/// Hands control of an unplaced tower to the delegate until tick `expires`,
+/// the reward accrued so far goes to the owner
+pub fn handle_delegate_tower(
+    player: &mut TDPlayer,
+    pkey: &[u64; 4],
+    iid: &[u64; 4],
+    delegate: &[u64; 2],
+    share: u64,
+    expires: u64,
+) -> Result<(), u32> {
+    if share > 100 {
+        return Err(ERROR_INVALID_SHARE);
+    }
+    check_not_delegated(iid[0])?;
+    if find_placed_tower(iid).is_some() {
+        return Err(ERROR_TOWER_ALREADY_PLACED);
+    }
+    let owner = TDPlayer::pkey_to_pid(pkey);
+    if owner == *delegate {
+        return Err(ERROR_INVALID_RECIPIENT);
+    }
+    let mut delegations = Delegations::get();
+    if delegations.0.len() as u64 >= CONFIG.max_delegations {
+        return Err(ERROR_DELEGATION_LIMIT);
+    }
+    let mut inventory_obj = load_inventory_object(iid).unwrap();
+    player.data.reward += inventory_obj.reward;
+    inventory_obj.reward = 0;
+    store_inventory_object(&inventory_obj);
+    if TDPlayer::get_from_pid(delegate).is_none() {
+        let mut player = TDPlayer::new_from_pid(*delegate);
+        player.nonce = 1;
+        player.store();
+    }
+    delegations.0.push(Delegation {
+        tower_id: iid[0],
+        owner,
+        delegate: *delegate,
+        share,
+        expires,
+    });
+    delegations.store();
+    Ok(())
+}
+
+/// Settles the reward split of delegations that end at `tick` and returns control to the owners
+pub fn expire_delegations(tick: u64) {
+    let mut delegations = Delegations::get();
+    let (expired, active): (Vec<Delegation>, Vec<Delegation>) =
+        delegations.0.into_iter().partition(|d| d.expires <= tick);
+    if expired.is_empty() {
+        return;
+    }
+    for d in expired.iter() {
+        let iid = crate::game::object::to_full_obj_id(d.tower_id);
+        let mut inventory_obj = load_inventory_object(&iid).unwrap();
+        let (owner_part, delegate_part) = d.split(inventory_obj.reward);
+        inventory_obj.reward = 0;
+        store_inventory_object(&inventory_obj);
+        credit_reward(&d.owner, owner_part);
+        credit_reward(&d.delegate, delegate_part);
+    }
+    delegations.0 = active;
+    delegations.store();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_control;
+    use super::check_not_delegated;
+    use super::expire_delegations;
+    use super::find_delegation;
+    use super::handle_delegate_tower;
+    use super::Delegation;
+    use super::Delegations;
+    use super::ERROR_DELEGATION_LIMIT;
+    use super::ERROR_INVALID_RECIPIENT;
+    use super::ERROR_INVALID_SHARE;
+    use super::ERROR_TOWER_ALREADY_PLACED;
+    use super::ERROR_TOWER_DELEGATED;
+    use crate::config::GameDirection;
+    use crate::config::CONFIG;
+    use crate::game::object::to_full_obj_id;
+    use crate::game::object::InventoryObject;
+    use crate::game::state::handle_collect_rewards;
+    use crate::game::state::handle_place_tower;
+    use crate::game::testing;
+    use crate::player::TDPlayer;
+
+    const OWNER: [u64; 4] = [0, 1, 2, 0];
+    const DELEGATE: [u64; 4] = [0, 0x3a, 0x4b, 0];
+    const STRANGER: [u64; 4] = [0, 5, 6, 0];
+
+    fn set_reward(tower_id: u64, reward: u64) {
+        let mut obj = InventoryObject::<GameDirection>::get(&to_full_obj_id(tower_id)).unwrap();
+        obj.reward = reward;
+        obj.store();
+    }
+
+    #[test]
+    fn test_split() {
+        let d = Delegation { tower_id: 1, owner: [1, 2], delegate: [3, 4], share: 30, expires: 0 };
+        assert_eq!(d.split(101), (71, 30));
+        assert_eq!(d.split(0), (0, 0));
+        assert_eq!(Delegation { share: 100, ..d.clone() }.split(u64::MAX), (0, u64::MAX));
+    }
+
+    #[test]
+    fn test_delegate_tower() {
+        let _guard = testing::setup();
+        let (owner, delegate) = ([1, 2], [0x3a, 0x4b]);
+        testing::new_player(owner, 0);
+        testing::mint(1, owner);
+        testing::mint(2, owner);
+        let iid = to_full_obj_id(1);
+        handle_place_tower(&to_full_obj_id(2), 0, testing::buildable_tile(0)).unwrap();
+        let mut player = TDPlayer::get_from_pid(&owner).unwrap();
+        set_reward(1, 40);
+
+        assert_eq!(handle_delegate_tower(&mut player, &OWNER, &iid, &delegate, 101, 10), Err(ERROR_INVALID_SHARE));
+        assert_eq!(handle_delegate_tower(&mut player, &OWNER, &iid, &owner, 30, 10), Err(ERROR_INVALID_RECIPIENT));
+        assert_eq!(
+            handle_delegate_tower(&mut player, &OWNER, &to_full_obj_id(2), &delegate, 30, 10),
+            Err(ERROR_TOWER_ALREADY_PLACED)
+        );
+        handle_delegate_tower(&mut player, &OWNER, &iid, &delegate, 30, 10).unwrap();
+        assert_eq!(player.data.reward, 40);
+        assert_eq!(InventoryObject::<GameDirection>::get(&iid).unwrap().reward, 0);
+        assert_eq!(TDPlayer::get_from_pid(&delegate).unwrap().nonce, 1);
+        assert_eq!(find_delegation(1).unwrap().expires, 10);
+        assert_eq!(
+            handle_delegate_tower(&mut player, &OWNER, &iid, &delegate, 30, 10),
+            Err(ERROR_TOWER_DELEGATED)
+        );
+
+        // only the delegate controls the tower now
+        let delegate_player = TDPlayer::get_from_pid(&delegate).unwrap();
+        assert_eq!(check_control(&delegate_player, &DELEGATE, 1), Ok(()));
+        assert_eq!(check_control(&player, &OWNER, 1), Err(ERROR_TOWER_DELEGATED));
+        assert_eq!(check_control(&player, &STRANGER, 1), Err(ERROR_TOWER_DELEGATED));
+        assert_eq!(check_not_delegated(1), Err(ERROR_TOWER_DELEGATED));
+        assert_eq!(check_not_delegated(2), Ok(()));
+    }
+
+    #[test]
+    fn test_reward_split_and_expiry() {
+        let _guard = testing::setup();
+        let (owner, delegate) = ([1, 2], [0x3a, 0x4b]);
+        testing::new_player(owner, 0);
+        testing::new_player(delegate, 0);
+        testing::mint(1, owner);
+        let mut player = TDPlayer::get_from_pid(&owner).unwrap();
+        let iid = to_full_obj_id(1);
+        handle_delegate_tower(&mut player, &OWNER, &iid, &delegate, 30, 10).unwrap();
+        player.store();
+
+        // the delegate collects its share and the owner part is credited directly
+        set_reward(1, 100);
+        let mut delegate_player = TDPlayer::get_from_pid(&delegate).unwrap();
+        handle_collect_rewards(&mut delegate_player, &DELEGATE, &iid).unwrap();
+        assert_eq!(delegate_player.data.reward, 30);
+        assert_eq!(TDPlayer::get_from_pid(&owner).unwrap().data.reward, 70);
+        let mut stranger = testing::new_player([5, 6], 0);
+        assert_eq!(handle_collect_rewards(&mut stranger, &STRANGER, &iid), Err(ERROR_TOWER_DELEGATED));
+        delegate_player.store();
+
+        set_reward(1, 50);
+        expire_delegations(9);
+        assert!(find_delegation(1).is_some());
+        expire_delegations(10);
+        assert!(Delegations::get().0.is_empty());
+        assert_eq!(TDPlayer::get_from_pid(&owner).unwrap().data.reward, 70 + 35);
+        assert_eq!(TDPlayer::get_from_pid(&delegate).unwrap().data.reward, 30 + 15);
+        assert_eq!(InventoryObject::<GameDirection>::get(&iid).unwrap().reward, 0);
+        let player = TDPlayer::get_from_pid(&owner).unwrap();
+        assert_eq!(check_control(&player, &OWNER, 1), Ok(()));
+    }
+
+    #[test]
+    fn test_delegation_limit() {
+        let _guard = testing::setup();
+        let (owner, delegate) = ([1, 2], [0x3a, 0x4b]);
+        testing::new_player(owner, 0);
+        testing::mint(1, owner);
+        testing::mint(2, owner);
+        let d = Delegation { tower_id: 0, owner: [5, 6], delegate, share: 0, expires: 10 };
+        let others = (0..CONFIG.max_delegations - 1).map(|id| Delegation { tower_id: 100 + id, ..d.clone() });
+        Delegations(others.collect()).store();
+        let mut player = TDPlayer::get_from_pid(&owner).unwrap();
+        handle_delegate_tower(&mut player, &OWNER, &to_full_obj_id(1), &delegate, 30, 10).unwrap();
+        assert_eq!(
+            handle_delegate_tower(&mut player, &OWNER, &to_full_obj_id(2), &delegate, 30, 10),
+            Err(ERROR_DELEGATION_LIMIT)
+        );
+        assert!(find_delegation(2).is_none());
+        assert_eq!(Delegations::get().0.len() as u64, CONFIG.max_delegations);
+    }
+}
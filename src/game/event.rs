@@ -4,4 +4,15 @@ use serde::Serialize;
 #[derive(Clone, Serialize)]
 pub enum Event {
     Attack((i64, i64), (i64, i64), u64), // bullet-type, src, target
+    Withdraw(u64, String), // amount, L1 address
+    WithdrawQueued(u64, String), // amount, L1 address
+    Achievement([String; 2], u64), // player id, achievement
+    TowerEvicted([String; 2], String), // owner id, tower id
+}
+
+/// Events that do not belong to an arena, kept until the next step
+pub static mut EVENTS: Vec<Event> = vec![];
+
+pub fn emit(event: Event) {
+    unsafe { EVENTS.push(event) };
 }
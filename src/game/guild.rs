@@ -0,0 +1,312 @@
+use super::bigint_serializer;
+use super::delegation::check_not_delegated;
+use super::market::pid_serializer;
+use super::state::find_placed_tower;
+use super::state::handle_upgrade_inventory;
+use super::ERROR_ALREADY_IN_GUILD;
+use super::ERROR_GUILD_EXISTS;
+use super::ERROR_GUILD_NOT_FOUND;
+use super::ERROR_INVALID_GUILD_ID;
+use super::ERROR_NOT_ENOUGH_REWARD;
+use super::ERROR_NOT_GUILD_OFFICER;
+use super::ERROR_NOT_IN_GUILD;
+use super::ERROR_NOT_GUILD_TOWER;
+use super::ERROR_TOWER_ALREADY_PLACED;
+use crate::config::GameDirection;
+use crate::game::object::to_full_obj_id;
+use crate::game::object::InventoryObject;
+use crate::player::bigint_array_serializer;
+use crate::player::TDPlayer;
+use crate::MERKLE_MAP;
+use serde::Serialize;
+
+pub const GUILD_MEMBER: u64 = 0;
+pub const GUILD_OFFICER: u64 = 1;
+pub const GUILD_LEADER: u64 = 2;
+
+/// Guild towers are owned by [GuildID, GUILD_OWNER]
+pub const GUILD_OWNER: u64 = 0xffff_ffff_ffff_ff08;
+
+pub fn to_guild_key(id: u64) -> [u64; 4] {
+    [id, 0xffff, 0xff01, 0xff08]
+}
+
+#[derive(Clone, Serialize)]
+pub struct GuildMember {
+    #[serde(serialize_with = "pid_serializer")]
+    pub pid: [u64; 2],
+    pub role: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Guild {
+    #[serde(serialize_with = "bigint_serializer")]
+    pub id: u64,
+    pub members: Vec<GuildMember>,
+    pub treasury: u64,
+    #[serde(serialize_with = "bigint_array_serializer")]
+    pub towers: Vec<u64>,
+}
+
+impl Guild {
+    pub fn get(id: u64) -> Option<Self> {
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        let data = kvpair.get(&to_guild_key(id));
+        if data.is_empty() {
+            None
+        } else {
+            let mut u64data = data.iter();
+            let mut next = || *u64data.next().unwrap();
+            let treasury = next();
+            let members_len = next();
+            let members = (0..members_len)
+                .map(|_| GuildMember {
+                    pid: [next(), next()],
+                    role: next(),
+                })
+                .collect();
+            let towers_len = next();
+            let towers = (0..towers_len).map(|_| next()).collect();
+            Some(Guild {
+                id,
+                members,
+                treasury,
+                towers,
+            })
+        }
+    }
+    pub fn store(&self) {
+        let mut data = vec![self.treasury, self.members.len() as u64];
+        for m in self.members.iter() {
+            data.extend_from_slice(&[m.pid[0], m.pid[1], m.role]);
+        }
+        data.push(self.towers.len() as u64);
+        data.extend_from_slice(&self.towers);
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        kvpair.set(&to_guild_key(self.id), data.as_slice());
+    }
+    pub fn role_of(&self, pid: &[u64; 2]) -> Option<u64> {
+        self.members.iter().find(|m| m.pid == *pid).map(|m| m.role)
+    }
+}
+
+/// The guild of the player
+fn guild_of(player: &TDPlayer) -> Result<Guild, u32> {
+    if player.data.guild == 0 {
+        return Err(ERROR_NOT_IN_GUILD);
+    }
+    Ok(Guild::get(player.data.guild).unwrap())
+}
+
+fn check_officer(guild: &Guild, pkey: &[u64; 4]) -> Result<(), u32> {
+    match guild.role_of(&TDPlayer::pkey_to_pid(pkey)) {
+        Some(GUILD_OFFICER) | Some(GUILD_LEADER) => Ok(()),
+        _ => Err(ERROR_NOT_GUILD_OFFICER),
+    }
+}
+
+/// Whether the tower belongs to the guild of the player
+pub fn is_guild_tower(player: &TDPlayer, tower_id: u64) -> bool {
+    guild_of(player).is_ok_and(|guild| guild.towers.contains(&tower_id))
+}
+
+/// Officers place and drop the towers of their guild
+pub fn check_guild_control(player: &TDPlayer, pkey: &[u64; 4], tower_id: u64) -> Result<(), u32> {
+    let guild = guild_of(player)?;
+    if !guild.towers.contains(&tower_id) {
+        return Err(ERROR_NOT_GUILD_TOWER);
+    }
+    check_officer(&guild, pkey)
+}
+
+pub fn handle_create_guild(player: &mut TDPlayer, pkey: &[u64; 4], id: u64) -> Result<(), u32> {
+    if id == 0 {
+        return Err(ERROR_INVALID_GUILD_ID);
+    }
+    if player.data.guild != 0 {
+        return Err(ERROR_ALREADY_IN_GUILD);
+    }
+    if Guild::get(id).is_some() {
+        return Err(ERROR_GUILD_EXISTS);
+    }
+    let guild = Guild {
+        id,
+        members: vec![GuildMember {
+            pid: TDPlayer::pkey_to_pid(pkey),
+            role: GUILD_LEADER,
+        }],
+        treasury: 0,
+        towers: vec![],
+    };
+    guild.store();
+    player.data.guild = id;
+    Ok(())
+}
+
+pub fn handle_join_guild(player: &mut TDPlayer, pkey: &[u64; 4], id: u64) -> Result<(), u32> {
+    if player.data.guild != 0 {
+        return Err(ERROR_ALREADY_IN_GUILD);
+    }
+    let mut guild = Guild::get(id).ok_or(ERROR_GUILD_NOT_FOUND)?;
+    guild.members.push(GuildMember {
+        pid: TDPlayer::pkey_to_pid(pkey),
+        role: GUILD_MEMBER,
+    });
+    guild.store();
+    player.data.guild = id;
+    Ok(())
+}
+
+pub fn handle_deposit_guild(player: &mut TDPlayer, amount: u64) -> Result<(), u32> {
+    let mut guild = guild_of(player)?;
+    if player.data.reward < amount {
+        return Err(ERROR_NOT_ENOUGH_REWARD);
+    }
+    player.data.reward -= amount;
+    guild.treasury += amount;
+    guild.store();
+    Ok(())
+}
+
+/// Only the leader appoints officers
+pub fn handle_promote_officer(player: &TDPlayer, pkey: &[u64; 4], member: &[u64; 2]) -> Result<(), u32> {
+    let mut guild = guild_of(player)?;
+    if guild.role_of(&TDPlayer::pkey_to_pid(pkey)) != Some(GUILD_LEADER) {
+        return Err(ERROR_NOT_GUILD_OFFICER);
+    }
+    let m = guild
+        .members
+        .iter_mut()
+        .find(|m| m.pid == *member)
+        .ok_or(ERROR_NOT_IN_GUILD)?;
+    if m.role == GUILD_MEMBER {
+        m.role = GUILD_OFFICER;
+    }
+    guild.store();
+    Ok(())
+}
+
+/// Moves an unplaced tower of a member into the guild, its pending reward goes to the member
+pub fn handle_donate_tower(player: &mut TDPlayer, iid: &[u64; 4]) -> Result<(), u32> {
+    let mut guild = guild_of(player)?;
+    check_not_delegated(iid[0])?;
+    if find_placed_tower(iid).is_some() {
+        return Err(ERROR_TOWER_ALREADY_PLACED);
+    }
+    let mut inventory_obj = InventoryObject::<GameDirection>::get(iid).unwrap();
+    player.data.reward += inventory_obj.reward;
+    inventory_obj.reward = 0;
+    let tower = inventory_obj.object.get_the_tower_mut();
+    tower.owner = [guild.id, GUILD_OWNER];
+    inventory_obj.store();
+    player.data.remove_tower(iid[0]);
+    guild.towers.push(iid[0]);
+    guild.store();
+    Ok(())
+}
+
+/// Officers upgrade guild towers with the treasury, the level a guild tower reaches does
+/// not count for the achievements of the officer
+pub fn handle_upgrade_guild_tower(player: &TDPlayer, pkey: &[u64; 4], tower_id: u64, recipe: u64) -> Result<(), u32> {
+    let mut guild = guild_of(player)?;
+    check_officer(&guild, pkey)?;
+    if !guild.towers.contains(&tower_id) {
+        return Err(ERROR_NOT_GUILD_TOWER);
+    }
+    handle_upgrade_inventory(&to_full_obj_id(tower_id), recipe, Some(&mut guild.treasury))?;
+    guild.store();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::build_tower;
+    use crate::config::upgrade_cost;
+    use crate::config::GameCoordinate;
+    use crate::config::MAX_LEVEL;
+    use crate::config::RECIPE_LEVEL_UP;
+    use crate::game::delegation::check_control;
+    use crate::game::object::Object;
+    use crate::game::state::State;
+    use crate::game::testing;
+    use crate::game::ERROR_GUILD_TOWER_ON_PREMIUM;
+    use crate::tile::coordinate::Coordinate;
+    use crate::tile::coordinate::TileType;
+
+    #[test]
+    fn test_create_and_join_guild() {
+        let _guard = testing::setup();
+        let (leader, member) = ([0, 0x51, 1, 0], [0, 0x51, 2, 0]);
+        let mut player = testing::new_player([0x51, 1], 0);
+        assert_eq!(handle_create_guild(&mut player, &leader, 0), Err(ERROR_INVALID_GUILD_ID));
+        handle_create_guild(&mut player, &leader, 0x51).unwrap();
+        assert_eq!(handle_create_guild(&mut player, &leader, 0x52), Err(ERROR_ALREADY_IN_GUILD));
+        let mut other = testing::new_player([0x51, 2], 0);
+        assert_eq!(handle_create_guild(&mut other, &member, 0x51), Err(ERROR_GUILD_EXISTS));
+        assert_eq!(handle_join_guild(&mut other, &member, 0x53), Err(ERROR_GUILD_NOT_FOUND));
+        handle_join_guild(&mut other, &member, 0x51).unwrap();
+        assert_eq!(handle_join_guild(&mut other, &member, 0x51), Err(ERROR_ALREADY_IN_GUILD));
+        let guild = Guild::get(0x51).unwrap();
+        assert_eq!(guild.role_of(&[0x51, 1]), Some(GUILD_LEADER));
+        assert_eq!(guild.role_of(&[0x51, 2]), Some(GUILD_MEMBER));
+    }
+
+    #[test]
+    fn test_officers_control_guild_towers() {
+        let _guard = testing::setup();
+        let (leader, member) = ([0, 0x54, 1, 0], [0, 0x54, 2, 0]);
+        let mut player = testing::new_player([0x54, 1], 0);
+        handle_create_guild(&mut player, &leader, 0x54).unwrap();
+        let mut other = testing::new_player([0x54, 2], 0);
+        handle_join_guild(&mut other, &member, 0x54).unwrap();
+        testing::mint(1, [0x54, 2]);
+        let iid = to_full_obj_id(1);
+        let mut obj = InventoryObject::<GameDirection>::get(&iid).unwrap();
+        obj.reward = 30;
+        obj.store();
+
+        handle_donate_tower(&mut other, &iid).unwrap();
+        assert_eq!(other.data.reward, 30);
+
+        // members need to be promoted before they place guild towers
+        assert_eq!(check_control(&other, &member, 1), Err(ERROR_NOT_GUILD_OFFICER));
+        handle_promote_officer(&player, &leader, &[0x54, 2]).unwrap();
+        check_control(&other, &member, 1).unwrap();
+        check_control(&player, &leader, 1).unwrap();
+    }
+
+    #[test]
+    fn test_guild_towers_stay_off_premium_tiles() {
+        let mut state = State::<GameCoordinate>::new(0xfe, 2, 1);
+        state.map.set_tile_type(0, TileType::Premium);
+        let mut tower = build_tower(1, GameCoordinate::directions()[0].clone());
+        tower.owner = [0x57, GUILD_OWNER];
+        let object = InventoryObject::new(to_full_obj_id(1), Object::Tower(tower));
+        let premium = state.map.coordinate_of_tile_index(0);
+        assert_eq!(state.place_tower_at(object, premium).err(), Some(ERROR_GUILD_TOWER_ON_PREMIUM));
+    }
+
+    #[test]
+    fn test_upgrade_guild_tower() {
+        let _guard = testing::setup();
+        let leader = [0, 0x55, 1, 0];
+        let mut player = testing::new_player([0x55, 1], 0);
+        handle_create_guild(&mut player, &leader, 0x55).unwrap();
+        testing::mint(1, [0x55, 1]);
+        testing::mint(2, [0x55, 1]);
+        handle_donate_tower(&mut player, &to_full_obj_id(1)).unwrap();
+        let cost = (1..MAX_LEVEL).map(|lvl| upgrade_cost(lvl).unwrap()).sum::<u64>();
+        player.data.reward = cost + 5;
+        handle_deposit_guild(&mut player, cost + 5).unwrap();
+
+        let upgrade = |id| handle_upgrade_guild_tower(&player, &leader, id, RECIPE_LEVEL_UP);
+        assert_eq!(upgrade(2), Err(ERROR_NOT_GUILD_TOWER));
+        for _ in 1..MAX_LEVEL {
+            upgrade(1).unwrap();
+        }
+        assert_eq!(Guild::get(0x55).unwrap().treasury, 5);
+        // a guild tower at max level does not unlock the achievement of the officer
+        assert_eq!(player.data.achievements, 0);
+    }
+}
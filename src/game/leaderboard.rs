@@ -0,0 +1,137 @@
+use super::bigint_serializer;
+use super::market::pid_serializer;
+use crate::config::CONFIG;
+use crate::MERKLE_MAP;
+use serde::Serialize;
+
+const LEADERBOARD_KEY: [u64; 4] = [0, 0xffff, 0xff01, 0xff07];
+
+#[derive(Clone, Serialize)]
+pub struct PlayerEntry {
+    #[serde(serialize_with = "pid_serializer")]
+    pub pid: [u64; 2],
+    pub earned: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct TowerEntry {
+    #[serde(serialize_with = "bigint_serializer")]
+    pub tower_id: u64,
+    pub kills: u64,
+}
+
+/// Top players by earned reward and top towers by kills, both sorted from the highest score
+#[derive(Clone, Serialize)]
+pub struct Leaderboard {
+    pub players: Vec<PlayerEntry>,
+    pub towers: Vec<TowerEntry>,
+}
+
+/// Moves the entry with the given key to its rank, ties keep the entry that got there first
+fn update_ranking<T, K: PartialEq>(
+    entries: &mut Vec<T>,
+    entry: T,
+    key: impl Fn(&T) -> K,
+    score: impl Fn(&T) -> u64,
+) {
+    let size = CONFIG.leaderboard_size as usize;
+    if let Some(pos) = entries.iter().position(|e| key(e) == key(&entry)) {
+        entries.remove(pos);
+    }
+    let rank = entries
+        .iter()
+        .position(|e| score(e) < score(&entry))
+        .unwrap_or(entries.len());
+    if rank < size {
+        entries.insert(rank, entry);
+        entries.truncate(size);
+    }
+}
+
+impl Leaderboard {
+    pub fn get() -> Self {
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        let data = kvpair.get(&LEADERBOARD_KEY);
+        let mut u64data = data.iter();
+        let mut next = || u64data.next().map_or(0, |x| *x);
+        let players_len = next();
+        let players = (0..players_len)
+            .map(|_| PlayerEntry {
+                pid: [next(), next()],
+                earned: next(),
+            })
+            .collect();
+        let towers_len = next();
+        let towers = (0..towers_len)
+            .map(|_| TowerEntry {
+                tower_id: next(),
+                kills: next(),
+            })
+            .collect();
+        Leaderboard { players, towers }
+    }
+    pub fn store(&self) {
+        let mut data = vec![self.players.len() as u64];
+        for p in self.players.iter() {
+            data.extend_from_slice(&[p.pid[0], p.pid[1], p.earned]);
+        }
+        data.push(self.towers.len() as u64);
+        for t in self.towers.iter() {
+            data.extend_from_slice(&[t.tower_id, t.kills]);
+        }
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        kvpair.set(&LEADERBOARD_KEY, data.as_slice());
+    }
+    pub fn update_player(&mut self, pid: [u64; 2], earned: u64) {
+        update_ranking(&mut self.players, PlayerEntry { pid, earned }, |e| e.pid, |e| e.earned);
+    }
+    pub fn update_tower(&mut self, tower_id: u64, kills: u64) {
+        update_ranking(&mut self.towers, TowerEntry { tower_id, kills }, |e| e.tower_id, |e| e.kills);
+    }
+    /// Drops a tower that left the game
+    pub fn remove_tower(&mut self, tower_id: u64) {
+        self.towers.retain(|e| e.tower_id != tower_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Leaderboard;
+    use crate::config::CONFIG;
+
+    fn towers(board: &Leaderboard) -> Vec<(u64, u64)> {
+        board.towers.iter().map(|t| (t.tower_id, t.kills)).collect()
+    }
+
+    #[test]
+    fn test_ranking() {
+        let mut board = Leaderboard { players: vec![], towers: vec![] };
+        board.update_tower(1, 5);
+        board.update_tower(2, 9);
+        board.update_tower(3, 5);
+        // ties keep the tower that got there first
+        assert_eq!(towers(&board), vec![(2, 9), (1, 5), (3, 5)]);
+        board.update_tower(3, 10);
+        assert_eq!(towers(&board), vec![(3, 10), (2, 9), (1, 5)]);
+        board.update_player([1, 1], 7);
+        board.update_player([2, 2], 3);
+        board.update_player([2, 2], 8);
+        let players = board.players.iter().map(|p| (p.pid, p.earned)).collect::<Vec<_>>();
+        assert_eq!(players, vec![([2, 2], 8), ([1, 1], 7)]);
+    }
+
+    #[test]
+    fn test_truncation() {
+        let size = CONFIG.leaderboard_size;
+        let mut board = Leaderboard { players: vec![], towers: vec![] };
+        for id in 1..=size + 2 {
+            board.update_tower(id, id);
+        }
+        assert_eq!(board.towers.len() as u64, size);
+        assert_eq!(board.towers[0].tower_id, size + 2);
+        // a score below the last rank of a full board is not listed
+        board.update_tower(100, 1);
+        assert!(board.towers.iter().all(|t| t.tower_id != 100));
+        assert_eq!(board.towers.last().unwrap().tower_id, 3);
+    }
+}
@@ -0,0 +1,283 @@
+use super::bigint_serializer;
+use super::state::find_placed_tower;
+use super::ERROR_INVALID_AMOUNT;
+use super::ERROR_INVALID_RECIPIENT;
+use super::ERROR_LISTING_NOT_FOUND;
+use super::ERROR_MARKET_FULL;
+use super::ERROR_NOT_ENOUGH_REWARD;
+use super::ERROR_NOT_SELLER;
+use super::ERROR_TOWER_ALREADY_PLACED;
+use crate::config::GameDirection;
+use crate::config::CONFIG;
+use crate::game::object::InventoryObject;
+use crate::player::TDPlayer;
+use crate::MERKLE_MAP;
+use serde::{ser::SerializeSeq, Serialize, Serializer};
+
+/// All listings live in one object so that they can be queried at once
+const MARKET_KEY: [u64; 4] = [0, 0xffff, 0xff01, 0xff03];
+
+pub fn pid_serializer<S>(pid: &[u64; 2], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut seq = serializer.serialize_seq(Some(2))?;
+    for element in pid {
+        seq.serialize_element(&element.to_string())?;
+    }
+    seq.end()
+}
+
+#[derive(Clone, Serialize)]
+pub struct Listing {
+    #[serde(serialize_with = "bigint_serializer")]
+    pub tower_id: u64,
+    #[serde(serialize_with = "pid_serializer")]
+    pub seller: [u64; 2],
+    #[serde(serialize_with = "bigint_serializer")]
+    pub price: u64,
+}
+
+/// Listed towers are held in escrow by the market until they are sold or cancelled
+#[derive(Clone, Serialize)]
+pub struct Market {
+    pub listings: Vec<Listing>,
+    pub fees: u64, // protocol fees collected so far
+}
+
+impl Market {
+    pub fn get() -> Self {
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        let data = kvpair.get(&MARKET_KEY);
+        if data.is_empty() {
+            Market {
+                listings: vec![],
+                fees: 0,
+            }
+        } else {
+            let mut u64data = data.iter();
+            let fees = *u64data.next().unwrap();
+            let len = *u64data.next().unwrap();
+            let listings = (0..len)
+                .map(|_| Listing {
+                    tower_id: *u64data.next().unwrap(),
+                    seller: [*u64data.next().unwrap(), *u64data.next().unwrap()],
+                    price: *u64data.next().unwrap(),
+                })
+                .collect();
+            Market { listings, fees }
+        }
+    }
+    pub fn store(&self) {
+        let mut data = vec![self.fees, self.listings.len() as u64];
+        for l in self.listings.iter() {
+            data.push(l.tower_id);
+            data.push(l.seller[0]);
+            data.push(l.seller[1]);
+            data.push(l.price);
+        }
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        kvpair.set(&MARKET_KEY, data.as_slice());
+    }
+    fn position(&self, tower_id: u64) -> Option<usize> {
+        self.listings.iter().position(|l| l.tower_id == tower_id)
+    }
+}
+
+pub fn market_fee(price: u64) -> u64 {
+    (price as u128 * CONFIG.market_fee_bps as u128 / 10000) as u64
+}
+
+/// Moves an unplaced tower from the seller's inventory into escrow,
+/// the pending tower reward goes to the seller
+pub fn handle_list_tower(player: &mut TDPlayer, pkey: &[u64; 4], iid: &[u64; 4], price: u64) -> Result<(), u32> {
+    if find_placed_tower(iid).is_some() {
+        return Err(ERROR_TOWER_ALREADY_PLACED);
+    }
+    if price == 0 {
+        return Err(ERROR_INVALID_AMOUNT);
+    }
+    let mut market = Market::get();
+    if market.listings.len() as u64 >= CONFIG.max_listings {
+        return Err(ERROR_MARKET_FULL);
+    }
+    let mut inventory_obj = InventoryObject::<GameDirection>::get(iid).unwrap();
+    player.data.reward += inventory_obj.reward;
+    inventory_obj.reward = 0;
+    inventory_obj.store();
+    player.data.remove_tower(iid[0]);
+    market.listings.push(Listing {
+        tower_id: iid[0],
+        seller: TDPlayer::pkey_to_pid(pkey),
+        price,
+    });
+    market.store();
+    Ok(())
+}
+
+pub fn handle_cancel_listing(player: &mut TDPlayer, pkey: &[u64; 4], tower_id: u64) -> Result<(), u32> {
+    let mut market = Market::get();
+    let index = market.position(tower_id).ok_or(ERROR_LISTING_NOT_FOUND)?;
+    if market.listings[index].seller != TDPlayer::pkey_to_pid(pkey) {
+        return Err(ERROR_NOT_SELLER);
+    }
+    player.data.add_tower(tower_id)?;
+    market.listings.remove(index);
+    market.store();
+    Ok(())
+}
+
+/// Pays the seller from the buyer's reward minus the protocol fee and hands over the tower
+pub fn handle_buy_tower(player: &mut TDPlayer, pkey: &[u64; 4], iid: &[u64; 4]) -> Result<(), u32> {
+    let mut market = Market::get();
+    let index = market.position(iid[0]).ok_or(ERROR_LISTING_NOT_FOUND)?;
+    let listing = market.listings[index].clone();
+    let buyer = TDPlayer::pkey_to_pid(pkey);
+    if listing.seller == buyer {
+        return Err(ERROR_INVALID_RECIPIENT);
+    }
+    if player.data.reward < listing.price {
+        return Err(ERROR_NOT_ENOUGH_REWARD);
+    }
+    player.data.add_tower(iid[0])?;
+    let fee = market_fee(listing.price);
+    let mut seller = TDPlayer::get_from_pid(&listing.seller).unwrap();
+    seller.data.reward += listing.price - fee;
+    seller.store();
+    player.data.reward -= listing.price;
+
+    let mut inventory_obj = InventoryObject::<GameDirection>::get(iid).unwrap();
+    let tower = inventory_obj.object.get_the_tower_mut();
+    tower.owner[0] = buyer[0];
+    tower.owner[1] = buyer[1];
+    inventory_obj.store();
+
+    market.listings.remove(index);
+    market.fees += fee;
+    market.store();
+    Ok(())
+}
+
+/// Moves the collected fees into the reward balance of the operator, who withdraws them as rewards
+pub fn handle_collect_fees(admin: &mut TDPlayer) {
+    let mut market = Market::get();
+    admin.data.reward += market.fees;
+    market.fees = 0;
+    market.store();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::handle_buy_tower;
+    use super::handle_cancel_listing;
+    use super::handle_collect_fees;
+    use super::handle_list_tower;
+    use super::market_fee;
+    use super::Listing;
+    use super::Market;
+    use super::ERROR_INVALID_AMOUNT;
+    use super::ERROR_INVALID_RECIPIENT;
+    use super::ERROR_LISTING_NOT_FOUND;
+    use super::ERROR_MARKET_FULL;
+    use super::ERROR_NOT_ENOUGH_REWARD;
+    use super::ERROR_NOT_SELLER;
+    use super::ERROR_TOWER_ALREADY_PLACED;
+    use crate::config::GameDirection;
+    use crate::config::CONFIG;
+    use crate::game::object::to_full_obj_id;
+    use crate::game::object::InventoryObject;
+    use crate::game::state::handle_place_tower;
+    use crate::game::testing;
+    use crate::player::Owner;
+    use crate::player::TDPlayer;
+
+    const SELLER: [u64; 4] = [0, 1, 2, 0];
+    const BUYER: [u64; 4] = [0, 3, 4, 0];
+
+    #[test]
+    fn test_list_and_cancel() {
+        let _guard = testing::setup();
+        let (seller, buyer) = ([1, 2], [3, 4]);
+        testing::new_player(seller, 0);
+        let mut other = testing::new_player(buyer, 0);
+        testing::mint(1, seller);
+        testing::mint(2, seller);
+        let iid = to_full_obj_id(1);
+        let mut obj = InventoryObject::<GameDirection>::get(&iid).unwrap();
+        obj.reward = 25;
+        obj.store();
+        handle_place_tower(&to_full_obj_id(2), 0, testing::buildable_tile(0)).unwrap();
+        let mut player = TDPlayer::get_from_pid(&seller).unwrap();
+
+        assert_eq!(handle_list_tower(&mut player, &SELLER, &iid, 0), Err(ERROR_INVALID_AMOUNT));
+        assert_eq!(handle_list_tower(&mut player, &SELLER, &to_full_obj_id(2), 100), Err(ERROR_TOWER_ALREADY_PLACED));
+        handle_list_tower(&mut player, &SELLER, &iid, 100).unwrap();
+        assert!(!player.owns(1));
+        assert_eq!(player.data.reward, 25);
+        assert_eq!(InventoryObject::<GameDirection>::get(&iid).unwrap().reward, 0);
+        let market = Market::get();
+        assert_eq!(market.listings.len(), 1);
+        assert_eq!((market.listings[0].seller, market.listings[0].price), (seller, 100));
+
+        assert_eq!(handle_cancel_listing(&mut other, &BUYER, 1), Err(ERROR_NOT_SELLER));
+        assert_eq!(handle_cancel_listing(&mut player, &SELLER, 3), Err(ERROR_LISTING_NOT_FOUND));
+        handle_cancel_listing(&mut player, &SELLER, 1).unwrap();
+        assert!(player.owns(1));
+        assert!(Market::get().listings.is_empty());
+    }
+
+    #[test]
+    fn test_buy_and_collect_fees() {
+        let _guard = testing::setup();
+        let (seller, buyer, admin) = ([1, 2], [3, 4], [5, 6]);
+        let price = 10_000;
+        let fee = market_fee(price);
+        assert!(fee > 0 && fee < price);
+        testing::new_player(seller, 0);
+        let mut player = testing::new_player(buyer, price - 1);
+        testing::mint(1, seller);
+        let iid = to_full_obj_id(1);
+        let mut owner = TDPlayer::get_from_pid(&seller).unwrap();
+        handle_list_tower(&mut owner, &SELLER, &iid, price).unwrap();
+        owner.store();
+
+        assert_eq!(handle_buy_tower(&mut owner, &SELLER, &iid), Err(ERROR_INVALID_RECIPIENT));
+        assert_eq!(handle_buy_tower(&mut player, &BUYER, &to_full_obj_id(2)), Err(ERROR_LISTING_NOT_FOUND));
+        assert_eq!(handle_buy_tower(&mut player, &BUYER, &iid), Err(ERROR_NOT_ENOUGH_REWARD));
+        player.data.reward = price + 7;
+        handle_buy_tower(&mut player, &BUYER, &iid).unwrap();
+        assert!(player.owns(1));
+        assert_eq!(player.data.reward, 7);
+        assert_eq!(TDPlayer::get_from_pid(&seller).unwrap().data.reward, price - fee);
+        let obj = InventoryObject::<GameDirection>::get(&iid).unwrap();
+        assert_eq!(obj.object.get_the_tower().owner, buyer);
+        let market = Market::get();
+        assert!(market.listings.is_empty());
+        assert_eq!(market.fees, fee);
+
+        let mut operator = testing::new_player(admin, 3);
+        handle_collect_fees(&mut operator);
+        assert_eq!(operator.data.reward, fee + 3);
+        assert_eq!(Market::get().fees, 0);
+    }
+
+    #[test]
+    fn test_list_when_market_full() {
+        let _guard = testing::setup();
+        let seller = [1, 2];
+        testing::new_player(seller, 0);
+        testing::mint(1, seller);
+        let mut market = Market::get();
+        for id in 0..CONFIG.max_listings - 1 {
+            market.listings.push(Listing { tower_id: 100 + id, seller: [5, 6], price: 1 });
+        }
+        market.store();
+        let mut player = TDPlayer::get_from_pid(&seller).unwrap();
+        handle_list_tower(&mut player, &SELLER, &to_full_obj_id(1), 100).unwrap();
+        testing::mint(2, seller);
+        let mut player = TDPlayer::get_from_pid(&seller).unwrap();
+        assert_eq!(handle_list_tower(&mut player, &SELLER, &to_full_obj_id(2), 100), Err(ERROR_MARKET_FULL));
+        assert!(player.owns(2));
+        assert_eq!(Market::get().listings.len() as u64, CONFIG.max_listings);
+    }
+}
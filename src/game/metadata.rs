@@ -0,0 +1,98 @@
+use super::market::pid_serializer;
+use crate::config::GameDirection;
+use crate::game::object::InventoryObject;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Attributes of a tower as shown off-chain, e.g. for a bridged NFT
+#[derive(Clone, Serialize)]
+pub struct TowerMetadata {
+    #[serde(serialize_with = "super::bigint_serializer")]
+    pub tower_id: u64,
+    pub kind: u64,
+    pub level: u64,
+    pub direction: u64,
+    pub range: u64,
+    pub power: u64,
+    pub cooldown: u64,
+    pub kills: u64,
+    #[serde(serialize_with = "pid_serializer")]
+    pub owner: [u64; 2],
+    pub hash: String,
+}
+
+impl TowerMetadata {
+    pub fn new(inventory_obj: &InventoryObject<GameDirection>) -> Self {
+        let tower = inventory_obj.object.get_the_tower();
+        let mut metadata = TowerMetadata {
+            tower_id: inventory_obj.object_id[0],
+            kind: tower.kind,
+            level: tower.lvl,
+            direction: tower.direction().clone().into(),
+            range: tower.range,
+            power: tower.power,
+            cooldown: tower.cooldown,
+            kills: inventory_obj.kills,
+            owner: tower.owner,
+            hash: String::new(),
+        };
+        metadata.hash = metadata
+            .hash()
+            .iter()
+            .fold(String::from("0x"), |hex, b| hex + &format!("{:02x}", b));
+        metadata
+    }
+    /// The attributes in the order they are hashed
+    pub fn attributes(&self) -> [u64; 10] {
+        [
+            self.tower_id,
+            self.kind,
+            self.level,
+            self.direction,
+            self.range,
+            self.power,
+            self.cooldown,
+            self.kills,
+            self.owner[0],
+            self.owner[1],
+        ]
+    }
+    /// SHA-256 of the attributes, each as 8 little endian bytes
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        for a in self.attributes() {
+            hasher.update(a.to_le_bytes());
+        }
+        hasher.finalize().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TowerMetadata;
+    use crate::config::build_tower;
+    use crate::config::GameCoordinate;
+    use crate::game::object::to_full_obj_id;
+    use crate::game::object::InventoryObject;
+    use crate::game::object::Object;
+    use crate::tile::coordinate::Coordinate;
+
+    #[test]
+    fn test_metadata_hash() {
+        let mut tower = build_tower(1, GameCoordinate::directions()[1].clone());
+        tower.owner = [7, 8];
+        let mut inventory_obj = InventoryObject::new(to_full_obj_id(100), Object::Tower(tower));
+        inventory_obj.kills = 3;
+        let metadata = TowerMetadata::new(&inventory_obj);
+        let l = crate::config::tower_level(1).unwrap();
+        assert_eq!(metadata.attributes(), [100, 0, 1, 1, l.range, l.power, l.cooldown, 3, 7, 8]);
+        // reward and upgrade spend are not part of the metadata
+        inventory_obj.reward = 10;
+        inventory_obj.spent = 10;
+        assert_eq!(TowerMetadata::new(&inventory_obj).hash, metadata.hash);
+        assert_eq!(
+            metadata.hash,
+            "0x4c82e783680d2d51d79b2b56afe14e42ab561fd04e898f333e52e0ce527a3930"
+        );
+    }
+}
@@ -5,7 +5,7 @@ use std::slice::IterMut;
 use crate::MERKLE_MAP;
 use crate::{
     config::upgrade_tower,
-    tile::coordinate::{Coordinate, RectCoordinate, RectDirection},
+    config::TOWER_KIND_STANDARD,
 };
 
 
@@ -41,6 +41,7 @@ impl U64arraySerialize for Monster {
 
 #[derive(Clone, Serialize)]
 pub struct Tower<Direction: Clone + Serialize> {
+    pub kind: u64,
     pub lvl: u64,
     pub range: u64,
     pub power: u64,
@@ -51,16 +52,18 @@ pub struct Tower<Direction: Clone + Serialize> {
     direction: Direction,
 }
 
-impl Tower<RectDirection> {
+impl<D: Clone + Serialize> Tower<D> {
     pub fn new(
+        kind: u64,
         lvl: u64,
         range: u64,
         power: u64,
         cooldown: u64,
         owner: [u64; 2],
-        direction: RectDirection,
+        direction: D,
     ) -> Self {
         Tower {
+            kind,
             lvl,
             range,
             power,
@@ -70,59 +73,12 @@ impl Tower<RectDirection> {
             direction,
         }
     }
-    pub fn range(&self, src: &RectCoordinate, target: &RectCoordinate) -> usize {
-        let src = src.repr();
-        let target = target.repr();
-        match self.direction {
-            RectDirection::Left => {
-                if src.1 == target.1 {
-                    if target.0 < src.0 {
-                        (src.0 - target.0) as usize
-                    } else {
-                        usize::max_value()
-                    }
-                } else {
-                    usize::max_value()
-                }
-            }
-            RectDirection::Right => {
-                if src.1 == target.1 {
-                    if target.0 > src.0 {
-                        (target.0 - src.0) as usize
-                    } else {
-                        usize::max_value()
-                    }
-                } else {
-                    usize::max_value()
-                }
-            }
-            RectDirection::Top => {
-                if src.0 == target.0 {
-                    if target.1 < src.1 {
-                        (src.1 - target.1) as usize
-                    } else {
-                        usize::max_value()
-                    }
-                } else {
-                    usize::max_value()
-                }
-            }
-            RectDirection::Bottom => {
-                if src.0 == target.0 {
-                    if target.1 > src.1 {
-                        (target.1 - src.1) as usize
-                    } else {
-                        usize::max_value()
-                    }
-                } else {
-                    usize::max_value()
-                }
-            }
-        }
+    pub fn direction(&self) -> &D {
+        &self.direction
     }
 }
 
-impl U64arraySerialize for Tower<RectDirection> {
+impl<D: Clone + Serialize + Into<u64> + From<u64>> U64arraySerialize for Tower<D> {
     fn to_u64_array(&self) -> Vec<u64> {
         vec![
             self.lvl,
@@ -131,19 +87,18 @@ impl U64arraySerialize for Tower<RectDirection> {
             self.cooldown,
             self.owner[0],
             self.owner[1],
-            self.direction.clone() as u64,
+            self.direction.clone().into(),
         ]
     }
     fn from_u64_array(data: &mut IterMut<u64>) -> Self {
-        let directions = RectCoordinate::directions();
-        Self::new(
-            *(data.next().unwrap()),
-            *data.next().unwrap(),
-            *data.next().unwrap(),
-            *data.next().unwrap(),
-            [*data.next().unwrap(), *data.next().unwrap()],
-            directions[*data.next().unwrap() as usize].clone(),
-        )
+        let lvl = *(data.next().unwrap());
+        let range = *data.next().unwrap();
+        let power = *data.next().unwrap();
+        let cooldown = *data.next().unwrap();
+        let owner = [*data.next().unwrap(), *data.next().unwrap()];
+        let direction = D::from(*data.next().unwrap());
+        // the kind is kept in the extension of the inventory object
+        Self::new(TOWER_KIND_STANDARD, lvl, range, power, cooldown, owner, direction)
     }
 }
 
@@ -217,7 +172,7 @@ pub enum Object<Direction: Clone + Serialize> {
     Collector(Collector),
 }
 
-impl U64arraySerialize for Object<RectDirection> {
+impl<D: Clone + Serialize + Into<u64> + From<u64>> U64arraySerialize for Object<D> {
     fn to_u64_array(&self) -> Vec<u64> {
         let (mut data, t) = match self {
             Object::Monster(o) => (o.to_u64_array(), 0),
@@ -242,8 +197,8 @@ impl U64arraySerialize for Object<RectDirection> {
     }
 }
 
-impl Object<RectDirection> {
-    pub fn get_the_tower(&self) -> &Tower<RectDirection> {
+impl<D: Clone + Serialize> Object<D> {
+    pub fn get_the_tower(&self) -> &Tower<D> {
         match self {
             Object::Tower(t) => t,
             _ => {
@@ -251,7 +206,7 @@ impl Object<RectDirection> {
             }
         }
     }
-    pub fn get_the_tower_mut(&mut self) -> &mut Tower<RectDirection> {
+    pub fn get_the_tower_mut(&mut self) -> &mut Tower<D> {
         match self {
             Object::Tower(t) => t,
             _ => {
@@ -260,7 +215,7 @@ impl Object<RectDirection> {
         }
     }
 
-    pub fn upgrade(&mut self) {
+    pub fn upgrade(&mut self) -> bool {
         match self {
             Object::Tower(t) => upgrade_tower(t),
             _ => {
@@ -271,23 +226,52 @@ impl Object<RectDirection> {
 }
 
 #[derive(Clone, Serialize)]
-pub struct InventoryObject {
+pub struct InventoryObject<D: Clone + Serialize> {
     pub object_id: [u64; 4],
-    pub object: Object<RectDirection>,
+    pub object: Object<D>,
     pub reward: u64,
+    pub kills: u64, // lifetime kills of the tower
+    pub spent: u64, // reward spent on upgrades so far
+    pub rent_due: u64, // rent the tower could not pay yet
+    pub locked: bool, // withdrawn to L1 until deposited back
 }
 
-impl InventoryObject {
-    pub fn new(object_id: [u64; 4], object: Object<RectDirection>) -> Self {
+impl<D: Clone + Serialize> InventoryObject<D> {
+    pub fn new(object_id: [u64; 4], object: Object<D>) -> Self {
         Self {
             object_id,
             object,
             reward: 0,
+            kills: 0,
+            spent: 0,
+            rent_due: 0,
+            locked: false,
         }
     }
 }
 
-impl U64arraySerialize for InventoryObject {
+impl<D: Clone + Serialize> InventoryObject<D> {
+    /// Fields added after the original layout, in the order they were introduced.
+    /// Records stored by older versions lack some of them and decode with defaults.
+    pub fn extension(&self) -> Vec<u64> {
+        vec![self.object.get_the_tower().kind, self.kills, self.spent, self.rent_due, self.locked as u64]
+    }
+    /// Pays the rent owed by the tower from its reward, as far as the reward goes
+    pub fn pay_rent_due(&mut self) {
+        let paid = self.reward.min(self.rent_due);
+        self.reward -= paid;
+        self.rent_due -= paid;
+    }
+    pub fn set_extension(&mut self, ext: &[u64]) {
+        self.object.get_the_tower_mut().kind = ext.first().map_or(TOWER_KIND_STANDARD, |x| *x);
+        self.kills = ext.get(1).map_or(0, |x| *x);
+        self.spent = ext.get(2).map_or(0, |x| *x);
+        self.rent_due = ext.get(3).map_or(0, |x| *x);
+        self.locked = ext.get(4).is_some_and(|x| *x != 0);
+    }
+}
+
+impl<D: Clone + Serialize + Into<u64> + From<u64>> U64arraySerialize for InventoryObject<D> {
     fn to_u64_array(&self) -> Vec<u64> {
         let mut data = self.object.to_u64_array();
         data.push(self.reward);
@@ -301,12 +285,16 @@ impl U64arraySerialize for InventoryObject {
         InventoryObject {
            object_id: to_full_obj_id(oid),
            reward,
+           kills: 0, // read from the extension section of the arena
+           spent: 0,
+           rent_due: 0,
+           locked: false,
            object,
         }
     }
 }
 
-impl InventoryObject {
+impl<D: Clone + Serialize + Into<u64> + From<u64>> InventoryObject<D> {
     pub fn get(object_id: &[u64; 4]) -> Option<Self> {
         let kvpair = unsafe { &mut MERKLE_MAP };
         zkwasm_rust_sdk::dbg!("get object with oid {:?}\n", object_id);
@@ -317,11 +305,11 @@ impl InventoryObject {
         } else {
             let mut slice_iter = data.as_mut_slice().iter_mut();
             let o = Object::from_u64_array(&mut slice_iter);
-            let inventory_obj = InventoryObject {
-                object_id: object_id.clone(),
-                reward: *(slice_iter.next().unwrap()),
-                object: o,
-            };
+            let reward = *(slice_iter.next().unwrap());
+            let ext = slice_iter.map(|x| *x).collect::<Vec<_>>();
+            let mut inventory_obj = InventoryObject::new(object_id.clone(), o);
+            inventory_obj.reward = reward;
+            inventory_obj.set_extension(&ext);
             Some(inventory_obj)
         }
     }
@@ -330,7 +318,86 @@ impl InventoryObject {
         zkwasm_rust_sdk::dbg!("store object {:?}\n", oid);
         let mut data = self.object.to_u64_array();
         data.push(self.reward);
+        data.append(&mut self.extension());
         let kvpair = unsafe { &mut MERKLE_MAP };
         kvpair.set(&self.object_id, data.as_slice());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::to_full_obj_id;
+    use super::InventoryObject;
+    use super::Object;
+    use crate::config::build_tower;
+    use crate::config::GameCoordinate;
+    use crate::config::GameDirection;
+    use crate::config::TOWER_KIND_SNIPER;
+    use crate::config::TOWER_KIND_STANDARD;
+    use crate::game::state::to_arena_key;
+    use crate::game::state::State;
+    use crate::game::testing;
+    use crate::tile::coordinate::Coordinate;
+    use crate::MERKLE_MAP;
+
+    #[test]
+    fn test_decode_object_without_extension() {
+        let _guard = testing::setup();
+        let iid = to_full_obj_id(5);
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        // [type, lvl, range, power, cooldown, owner, direction, reward]
+        kvpair.set(&iid, &[1, 2, 4, 20, 4, 7, 8, 1, 30]);
+        let mut obj = InventoryObject::<GameDirection>::get(&iid).unwrap();
+        let tower = obj.object.get_the_tower();
+        assert_eq!((tower.kind, tower.lvl, tower.owner), (TOWER_KIND_STANDARD, 2, [7, 8]));
+        assert_eq!((obj.reward, obj.kills, obj.spent, obj.rent_due), (30, 0, 0, 0));
+
+        obj.object.get_the_tower_mut().kind = TOWER_KIND_SNIPER;
+        obj.store();
+        let obj = InventoryObject::<GameDirection>::get(&iid).unwrap();
+        assert_eq!(obj.object.get_the_tower().kind, TOWER_KIND_SNIPER);
+        assert_eq!(obj.reward, 30);
+    }
+
+    #[test]
+    fn test_arena_keeps_tower_extension() {
+        let _guard = testing::setup();
+        let mut state = State::<GameCoordinate>::new(0xfe, 4, 2);
+        for (i, kind) in [TOWER_KIND_SNIPER, TOWER_KIND_STANDARD].into_iter().enumerate() {
+            let mut tower = build_tower(3, GameCoordinate::directions()[0].clone());
+            tower.kind = kind;
+            let mut obj = InventoryObject::new(to_full_obj_id(i as u64 + 1), Object::Tower(tower));
+            obj.kills = 10 + i as u64;
+            obj.spent = 100 + i as u64;
+            let position = state.map.coordinate_of_tile_index(i * 2);
+            state.place_tower_at(obj, position).unwrap();
+        }
+        state.store();
+        let mut restored = State::<GameCoordinate>::new(0xfe, 4, 2);
+        assert!(restored.fetch());
+        let towers = restored.towers.iter().map(|t| (t.object.object.get_the_tower().kind, t.object.kills, t.object.spent));
+        assert_eq!(towers.collect::<Vec<_>>(), vec![(TOWER_KIND_SNIPER, 10, 100), (TOWER_KIND_STANDARD, 11, 101)]);
+    }
+
+    #[test]
+    fn test_decode_arena_without_extension() {
+        let _guard = testing::setup();
+        let mut state = State::<GameCoordinate>::new(0xfe, 4, 2);
+        let mut obj = InventoryObject::new(to_full_obj_id(3), Object::Tower(build_tower(2, GameCoordinate::directions()[0].clone())));
+        obj.object.get_the_tower_mut().kind = TOWER_KIND_SNIPER;
+        (obj.reward, obj.kills, obj.spent, obj.rent_due, obj.locked) = (30, 4, 50, 2, true);
+        state.place_tower_at(obj, state.map.coordinate_of_tile_index(1)).unwrap();
+        state.store();
+        // drop the extension section, the tower record ends with [reward, oid]
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        let mut data = kvpair.get(&to_arena_key(0xfe));
+        data.truncate(data.len() - 6);
+        kvpair.set(&to_arena_key(0xfe), &data);
+
+        let mut restored = State::<GameCoordinate>::new(0xfe, 4, 2);
+        assert!(restored.fetch());
+        let obj = &restored.towers[0].object;
+        assert_eq!(obj.object.get_the_tower().kind, TOWER_KIND_STANDARD);
+        assert_eq!((obj.reward, obj.kills, obj.spent, obj.rent_due, obj.locked), (30, 0, 0, 0, false));
+    }
+}
@@ -1,3 +1,4 @@
+use super::event::emit;
 use super::event::Event;
 use super::object::Collector;
 use super::object::Dropped;
@@ -6,45 +7,115 @@ use super::object::Object;
 use super::object::Spawner;
 use super::object::Tower;
 use super::ERROR_POSITION_OCCUPIED;
+use super::ERROR_POSITION_ON_PATH;
+use super::ERROR_POSITION_BLOCKED;
+use super::ERROR_POSITION_RESERVED;
+use super::ERROR_ARENA_NOT_FOUND;
+use super::ERROR_TOWER_ALREADY_PLACED;
+use super::ERROR_UNKNOWN_RECIPE;
+use super::ERROR_RECIPE_NOT_APPLICABLE;
+use super::ERROR_NOT_ENOUGH_REWARD;
+use super::ERROR_MAX_LEVEL;
+use super::ERROR_INVALID_RECIPIENT;
+use super::ERROR_TOWER_DELEGATED;
+use super::ERROR_FUSION_MISMATCH;
+use super::ERROR_TOWER_LIMIT;
+use super::ERROR_TOWERS_TOO_CLOSE;
+use super::ERROR_GUILD_TOWER_ON_PREMIUM;
+use super::auction::check_placement_rights;
+use super::delegation::check_not_delegated;
+use super::delegation::find_delegation;
+use super::delegation::credit_reward;
+use super::delegation::Delegations;
+use super::guild::Guild;
+use super::guild::GUILD_OWNER;
+use super::leaderboard::Leaderboard;
+use super::achievement::check_achievements;
+use crate::config::ACHIEVEMENT_KILLS;
+use crate::config::ACHIEVEMENT_TOWER_KILLS;
+use crate::config::ACHIEVEMENT_WAVE;
+use crate::config::WAVE_SIZE;
 use crate::player::TDPlayer;
 use crate::player::Owner;
 use crate::config::spawn_monster;
 use crate::config::CONFIG;
 use crate::config::SPWAN_INTERVAL;
-use crate::config::UPGRADE_COST;
+use crate::config::upgrade_cost;
+use crate::config::upgrade_tower;
+use crate::config::RECIPE_LEVEL_UP;
+use crate::config::TOWER_KIND_STANDARD;
+use crate::config::GameCoordinate;
+use crate::config::GameDirection;
 use crate::game::object::InventoryObject;
 use crate::tile::coordinate::Coordinate;
-use crate::tile::coordinate::RectCoordinate;
-use crate::tile::coordinate::RectDirection;
+use crate::tile::coordinate::Tile;
+use crate::tile::coordinate::TileType;
 use crate::tile::map::Map;
 use crate::tile::map::PositionedObject;
 use serde::Serialize;
-use zkwasm_rust_sdk::require;
 use crate::MERKLE_MAP;
 use crate::game::serialize::U64arraySerialize;
 use core::slice::IterMut;
 
-// The global state
+pub fn to_arena_key(arena: u64) -> [u64; 4] {
+    [arena, 0, 0, 0]
+}
+
+const TICK_KEY: [u64; 4] = [0, 0xffff, 0xff01, 0xff04];
+
+/// Number of steps the game has run so far
+pub fn current_tick() -> u64 {
+    let kvpair = unsafe { &mut MERKLE_MAP };
+    kvpair.get(&TICK_KEY).first().map_or(0, |x| *x)
+}
+
+pub fn advance_tick() -> u64 {
+    let tick = current_tick() + 1;
+    let kvpair = unsafe { &mut MERKLE_MAP };
+    kvpair.set(&TICK_KEY, &[tick]);
+    tick
+}
+
+// The state of one arena
 #[derive(Clone, Serialize)]
-pub struct State {
+pub struct State<C: Coordinate> {
+    pub arena: u64,
     #[serde(skip_serializing)]
     pub id_allocator: u64,
-    pub map: Map<RectCoordinate>,
-    pub monsters: Vec<PositionedObject<RectCoordinate, Monster>>,
-    pub drops: Vec<PositionedObject<RectCoordinate, Dropped>>,
-    pub collectors: Vec<PositionedObject<RectCoordinate, Collector>>,
-    pub spawners: Vec<PositionedObject<RectCoordinate, Spawner>>,
-    pub towers: Vec<PositionedObject<RectCoordinate, InventoryObject>>,
+    pub map: Map<C>,
+    pub monsters: Vec<PositionedObject<C, Monster>>,
+    pub drops: Vec<PositionedObject<C, Dropped>>,
+    pub collectors: Vec<PositionedObject<C, Collector>>,
+    pub spawners: Vec<PositionedObject<C, Spawner>>,
+    pub towers: Vec<PositionedObject<C, InventoryObject<C::Direction>>>,
     pub events: Vec<Event>,
 }
 
-impl State {
+impl<C: Coordinate> State<C> {
+    pub fn new(arena: u64, width: usize, height: usize) -> Self {
+        let tiles = (0..width * height).map(|_| Tile::new(None)).collect();
+        State {
+            arena,
+            id_allocator: 0,
+            map: Map::new(width, height, tiles),
+            monsters: vec![],
+            drops: vec![],
+            collectors: vec![],
+            spawners: vec![],
+            towers: vec![],
+            events: vec![],
+        }
+    }
     pub fn store(&self) {
         let kvpair = unsafe { &mut MERKLE_MAP };
         let monsters_data = self.monsters.iter().map(|x| x.to_u64_array()).flatten().collect::<Vec<u64>>();
         let spawners_data = self.spawners.iter().map(|x| x.to_u64_array()).flatten().collect::<Vec<u64>>();
         let towers_data = self.towers.iter().map(|x| x.to_u64_array()).flatten().collect::<Vec<u64>>();
-        let data = vec![vec![self.id_allocator, self.monsters.len() as u64, self.spawners.len() as u64, self.towers.len() as u64], monsters_data, spawners_data, towers_data]
+        // the extension fields of all towers follow, prefixed with their count per tower
+        let extension = self.towers.iter().map(|x| x.object.extension()).collect::<Vec<_>>();
+        let width = extension.first().map_or(0, |x| x.len()) as u64;
+        let extension_data = vec![vec![width], extension.concat()].concat();
+        let data = vec![vec![self.id_allocator, self.monsters.len() as u64, self.spawners.len() as u64, self.towers.len() as u64], monsters_data, spawners_data, towers_data, extension_data]
             .into_iter()
             .flatten()
             .collect::<Vec<_>>();
@@ -53,13 +124,13 @@ impl State {
         //zkwasm_rust_sdk::dbg!("spawners: {}\n", splen);
         let mlen = self.monsters.len();
         //zkwasm_rust_sdk::dbg!("monsters: {}\n", mlen);
-        kvpair.set(&[0,0,0,0], &data);
+        kvpair.set(&to_arena_key(self.arena), &data);
         let root = kvpair.merkle.root;
         //zkwasm_rust_sdk::dbg!("after store: {:?}\n", root);
     }
     pub fn fetch(&mut self) -> bool {
         let kvpair = unsafe { &mut MERKLE_MAP };
-        let mut data = kvpair.get(&[0,0,0,0]);
+        let mut data = kvpair.get(&to_arena_key(self.arena));
         if data.is_empty() {
             false
         } else {
@@ -74,27 +145,33 @@ impl State {
             self.spawners = Vec::with_capacity(spawners_len);
             self.towers = Vec::with_capacity(towers_len);
             for _ in 0..monsters_len {
-                let obj = PositionedObject::<RectCoordinate, Monster>::from_u64_array(&mut data);
+                let obj = PositionedObject::<C, Monster>::from_u64_array(&mut data);
                 self.monsters.push(obj);
             }
             for _ in 0..spawners_len {
-                let obj = PositionedObject::<RectCoordinate, Spawner>::from_u64_array(&mut data);
+                let obj = PositionedObject::<C, Spawner>::from_u64_array(&mut data);
                 self.map.set_occupy(&obj.position, 1);
                 self.spawners.push(obj);
             }
             for _ in 0..towers_len {
-                let obj = PositionedObject::<RectCoordinate, InventoryObject>::from_u64_array(&mut data);
+                let obj = PositionedObject::<C, InventoryObject<C::Direction>>::from_u64_array(&mut data);
                 self.map.set_occupy(&obj.position, 1);
                 self.towers.push(obj);
             }
+            // absent in arenas stored before towers were extended
+            let width = data.next().map_or(0, |x| *x as usize);
+            for tower in self.towers.iter_mut() {
+                let ext = data.by_ref().take(width).map(|x| *x).collect::<Vec<_>>();
+                tower.object.set_extension(&ext);
+            }
             true
         }
     }
     pub fn place_spawner_at(
         &mut self,
         object: Spawner,
-        position: RectCoordinate,
-    ) -> &PositionedObject<RectCoordinate, Spawner> {
+        position: C,
+    ) -> &PositionedObject<C, Spawner> {
         self.map.set_occupy(&position, 1);
         self.id_allocator += 1;
         self.spawners
@@ -105,8 +182,8 @@ impl State {
     pub fn place_collector_at(
         &mut self,
         object: Collector,
-        position: RectCoordinate,
-    ) -> &PositionedObject<RectCoordinate, Collector> {
+        position: C,
+    ) -> &PositionedObject<C, Collector> {
         self.map.set_occupy(&position, 1);
         self.id_allocator += 1;
         self.collectors
@@ -114,26 +191,43 @@ impl State {
         self.collectors.get(self.collectors.len() - 1).unwrap()
     }
 
+    /// Whether a tower of the owner can stand on the position, by tile type and occupancy
+    pub fn check_position(&self, owner: &[u64; 2], position: &C) -> Result<(), u32> {
+        match self.map.get_tile_type(position) {
+            TileType::Buildable => (),
+            TileType::Path => return Err(ERROR_POSITION_ON_PATH),
+            TileType::Blocked => return Err(ERROR_POSITION_BLOCKED),
+            TileType::Reserved => return Err(ERROR_POSITION_RESERVED),
+            // leases are held by players, so guilds can not use premium tiles
+            TileType::Premium if owner[1] == GUILD_OWNER => return Err(ERROR_GUILD_TOWER_ON_PREMIUM),
+            TileType::Premium => {
+                let tile = self.map.index_of_tile_coordinate(position) as u64;
+                check_placement_rights(self.arena, tile, owner)?;
+            }
+        }
+        if self.map.get_occupy(position) != 0 {
+            return Err(ERROR_POSITION_OCCUPIED);
+        }
+        Ok(())
+    }
+
     pub fn place_tower_at(
         &mut self,
-        object: InventoryObject,
-        position: RectCoordinate,
-    ) -> Result<&PositionedObject<RectCoordinate, InventoryObject>, u32> {
-        if self.map.get_occupy(&position) != 0 {
-            Err(ERROR_POSITION_OCCUPIED)
-        } else {
-            self.id_allocator += 1;
-            self.map.set_occupy(&position, 1);
-            self.towers
-                .push(PositionedObject::new(object, position, self.id_allocator));
-            Ok(self.towers.get(self.towers.len() - 1).unwrap())
-        }
+        object: InventoryObject<C::Direction>,
+        position: C,
+    ) -> Result<&PositionedObject<C, InventoryObject<C::Direction>>, u32> {
+        self.check_position(&object.object.get_the_tower().owner, &position)?;
+        self.id_allocator += 1;
+        self.map.set_occupy(&position, 1);
+        self.towers
+            .push(PositionedObject::new(object, position, self.id_allocator));
+        Ok(self.towers.get(self.towers.len() - 1).unwrap())
     }
 
     pub fn remove_tower_at(
         &mut self,
         index: usize,
-    ) -> PositionedObject<RectCoordinate, InventoryObject> {
+    ) -> PositionedObject<C, InventoryObject<C::Direction>> {
         let tower = self.towers[index].clone();
         self.map.set_occupy(&tower.position, 0);
         self.towers.swap_remove(index)
@@ -142,34 +236,34 @@ impl State {
     pub fn spawn_monster_at(
         &mut self,
         object: Monster,
-        position: RectCoordinate,
-    ) -> &PositionedObject<RectCoordinate, Monster> {
+        position: C,
+    ) -> &PositionedObject<C, Monster> {
         self.id_allocator += 1;
         self.monsters
             .push(PositionedObject::new(object, position, self.id_allocator));
         self.monsters.get(self.monsters.len() - 1).unwrap()
     }
 
-    pub fn remove_monster(&mut self, index: usize) -> PositionedObject<RectCoordinate, Monster> {
+    pub fn remove_monster(&mut self, index: usize) -> PositionedObject<C, Monster> {
         self.monsters.swap_remove(index)
     }
 
     pub fn spawn_dropped_at(
         &mut self,
         object: Dropped,
-        position: RectCoordinate,
-    ) -> &PositionedObject<RectCoordinate, Monster> {
+        position: C,
+    ) -> &PositionedObject<C, Monster> {
         self.id_allocator += 1;
         self.drops
             .push(PositionedObject::new(object, position, self.id_allocator));
         self.monsters.get(self.monsters.len() - 1).unwrap()
     }
 
-    pub fn remove_dropped(&mut self, index: usize) -> PositionedObject<RectCoordinate, Dropped> {
+    pub fn remove_dropped(&mut self, index: usize) -> PositionedObject<C, Dropped> {
         self.drops.swap_remove(index)
     }
 
-    pub fn spawn(&mut self, obj: PositionedObject<RectCoordinate, Object<RectDirection>>) {
+    pub fn spawn(&mut self, obj: PositionedObject<C, Object<C::Direction>>) {
         match obj.object {
             Object::Monster(m) => self.spawn_monster_at(m, obj.position),
             Object::Dropped(d) => self.spawn_dropped_at(d, obj.position),
@@ -178,16 +272,85 @@ impl State {
     }
 }
 
-pub fn handle_place_tower(iid: &[u64; 4], pos: usize) -> Result<(), u32> {
-    let global = unsafe { &mut crate::config::GLOBAL };
-    let inventory_obj = InventoryObject::get(iid);
+/// Returns the (arena, tower index) of a tower that is placed on any board
+pub fn find_placed_tower(iid: &[u64; 4]) -> Option<(usize, usize)> {
+    let arenas = unsafe { &crate::config::ARENAS };
+    for (arena, state) in arenas.iter().enumerate() {
+        let pos = state
+            .towers
+            .iter()
+            .position(|x| x.object.object_id == *iid);
+        if let Some(index) = pos {
+            return Some((arena, index));
+        }
+    }
+    None
+}
+
+/// A placed tower lives on the board, so read it from there when possible
+pub fn load_inventory_object(iid: &[u64; 4]) -> Option<InventoryObject<GameDirection>> {
+    let arenas = unsafe { &crate::config::ARENAS };
+    match find_placed_tower(iid) {
+        Some((arena, index)) => Some(arenas[arena].towers[index].object.clone()),
+        None => InventoryObject::get(iid),
+    }
+}
+
+/// Stores the object and refreshes its copy on the board if it is placed
+pub fn store_inventory_object(inventory_obj: &InventoryObject<GameDirection>) {
+    let arenas = unsafe { &mut crate::config::ARENAS };
+    if let Some((arena, index)) = find_placed_tower(&inventory_obj.object_id) {
+        let placed = &mut arenas[arena].towers[index].object;
+        let count = placed.object.get_the_tower().count;
+        *placed = inventory_obj.clone();
+        placed.object.get_the_tower_mut().count = count;
+    }
+    inventory_obj.store();
+}
+
+pub fn handle_place_tower(iid: &[u64; 4], arena: u64, pos: usize) -> Result<(), u32> {
+    let arenas = unsafe { &mut crate::config::ARENAS };
+    if find_placed_tower(iid).is_some() {
+        return Err(ERROR_TOWER_ALREADY_PLACED);
+    }
+    let global = arenas.get_mut(arena as usize).ok_or(ERROR_ARENA_NOT_FOUND)?;
+    let inventory_obj = InventoryObject::get(iid).unwrap();
     let position = global.map.coordinate_of_tile_index(pos);
-    global.place_tower_at(inventory_obj.unwrap(), position)?;
+    let owner = inventory_obj.object.get_the_tower().owner;
+    global.check_position(&owner, &position)?;
+    // keep a single owner from crowding the board or walling off a stretch of the path
+    let owned = global
+        .towers
+        .iter()
+        .filter(|t| t.object.object.get_the_tower().owner == owner)
+        .collect::<Vec<_>>();
+    if owned.len() as u64 >= CONFIG.max_towers_per_board {
+        return Err(ERROR_TOWER_LIMIT);
+    }
+    if owned
+        .iter()
+        .any(|t| GameCoordinate::distance(&t.position, &position) < CONFIG.min_tower_distance)
+    {
+        return Err(ERROR_TOWERS_TOO_CLOSE);
+    }
+    global.place_tower_at(inventory_obj, position)?;
     Ok(())
 }
 
-pub fn handle_update_inventory(iid: &[u64; 4], feature: u64, pid: &[u64; 2]) {
-    let mut inventory_obj = InventoryObject::get(iid);
+pub fn handle_update_inventory(iid: &[u64; 4], feature: u64, pid: &[u64; 2]) -> Result<(), u32> {
+    let mut player_opt = TDPlayer::get_from_pid(pid);
+    if let Some(player) = player_opt.as_mut() {
+        if !player.owns(iid[0]) {
+            player.data.add_tower(iid[0])?;
+            player.store()
+        }
+    } else {
+        let mut player = TDPlayer::new_from_pid(*pid);
+        player.data.add_tower(iid[0])?;
+        player.nonce = 1;
+        player.store()
+    }
+    let mut inventory_obj = InventoryObject::<GameDirection>::get(iid);
     if let Some(inventory_obj) = inventory_obj.as_mut() {
         let tower = inventory_obj.object.get_the_tower_mut();
         tower.owner[0] = pid[0];
@@ -200,80 +363,310 @@ pub fn handle_update_inventory(iid: &[u64; 4], feature: u64, pid: &[u64; 2]) {
         let inventory_obj = InventoryObject::new(iid.clone(), Object::Tower(tower));
         inventory_obj.store();
     }
-    let mut player_opt = TDPlayer::get_from_pid(pid);
-    if let Some(player) = player_opt.as_mut() {
-        if !player.owns(iid[0]) {
-            player.data.inventory.push(iid[0]);
-            player.store()
-        }
-    } else {
-        let mut player = TDPlayer::new_from_pid(*pid);
-        player.data.inventory.push(iid[0]);
-        player.nonce = 1;
-        player.store()
-    }
+    Ok(())
 }
 
-pub fn handle_withdraw_tower(nonce: u64, iid: &[u64; 4], pkey: &[u64; 4]) {
-    let inventory_obj = InventoryObject::get(iid);
-    if inventory_obj.is_none() {
-        unreachable!()
-    } else {
-        let obj = inventory_obj.unwrap().object.clone();
-        let tower = obj.get_the_tower();
-        unsafe {
-            zkwasm_rust_sdk::require(tower.owner[0] == pkey[1]);
-            zkwasm_rust_sdk::require(tower.owner[1] == pkey[2]);
-        }
-
-        let mut player = TDPlayer::get(pkey).unwrap();
-        player.check_and_inc_nonce(nonce);
-        let index_opt = player.data.inventory.iter().position(|&x| x == iid[0]);
-        if let Some(index) = index_opt {
-            player.data.inventory.swap_remove(index);
-            player.store()
-        } else {
-            player.store()
-        }
+/// Moves an unplaced tower to another player, the pending tower reward goes to the sender
+pub fn handle_transfer_tower(player: &mut TDPlayer, pkey: &[u64; 4], iid: &[u64; 4], pid: &[u64; 2]) -> Result<(), u32> {
+    if find_placed_tower(iid).is_some() {
+        return Err(ERROR_TOWER_ALREADY_PLACED);
     }
-}
+    if TDPlayer::pkey_to_pid(pkey) == *pid {
+        return Err(ERROR_INVALID_RECIPIENT);
+    }
+    let mut recipient = TDPlayer::get_from_pid(pid).unwrap_or_else(|| {
+        let mut recipient = TDPlayer::new_from_pid(*pid);
+        recipient.nonce = 1;
+        recipient
+    });
+    recipient.data.add_tower(iid[0])?;
+    let mut inventory_obj = InventoryObject::<GameDirection>::get(iid).unwrap();
+    player.data.reward += inventory_obj.reward;
+    inventory_obj.reward = 0;
+    let tower = inventory_obj.object.get_the_tower_mut();
+    tower.owner[0] = pid[0];
+    tower.owner[1] = pid[1];
+    inventory_obj.store();
 
+    player.data.remove_tower(iid[0]);
+    recipient.store();
+    Ok(())
+}
 
 pub fn handle_drop_tower(iid: &[u64; 4]) {
-    let global = unsafe { &mut crate::config::GLOBAL };
+    let arenas = unsafe { &mut crate::config::ARENAS };
     //let inventory_obj = InventoryObject::get(iid);
-    let pos = global
-        .towers
-        .iter()
-        .position(|x| x.object.object_id == *iid);
-    if let Some(index) = pos {
-        global.remove_tower_at(index);
+    if let Some((arena, index)) = find_placed_tower(iid) {
+        arenas[arena].remove_tower_at(index);
     }
 }
 
-pub fn handle_collect_rewards(player: &mut TDPlayer, iid: &[u64; 4]) {
+/// Delegated towers split the reward between owner and delegate, whoever collects it
+pub fn handle_collect_rewards(player: &mut TDPlayer, pkey: &[u64; 4], iid: &[u64; 4]) -> Result<(), u32> {
     //let inventory_obj = InventoryObject::get(iid);
-    let mut inventory_obj = InventoryObject::get(iid).unwrap();
-    player.data.reward += inventory_obj.reward;
+    let mut inventory_obj = load_inventory_object(iid).unwrap();
+    inventory_obj.pay_rent_due();
+    if let Some(d) = find_delegation(iid[0]) {
+        let pid = TDPlayer::pkey_to_pid(pkey);
+        let (owner_part, delegate_part) = d.split(inventory_obj.reward);
+        if pid == d.owner {
+            player.data.reward += owner_part;
+            credit_reward(&d.delegate, delegate_part);
+        } else if pid == d.delegate {
+            player.data.reward += delegate_part;
+            credit_reward(&d.owner, owner_part);
+        } else {
+            return Err(ERROR_TOWER_DELEGATED);
+        }
+    } else {
+        unsafe { zkwasm_rust_sdk::require(player.owns(iid[0])) };
+        player.data.reward += inventory_obj.reward;
+    }
     inventory_obj.reward = 0;
-    inventory_obj.store();
+    store_inventory_object(&inventory_obj);
+    Ok(())
 }
 
+pub fn handle_collect_all_rewards(player: &mut TDPlayer, pkey: &[u64; 4]) -> Result<(), u32> {
+    for tower_id in player.data.inventory.clone() {
+        handle_collect_rewards(player, pkey, &crate::game::object::to_full_obj_id(tower_id))?;
+    }
+    Ok(())
+}
 
+/// Destroys an unplaced tower and refunds its reward and part of its upgrade spend
+pub fn handle_burn_tower(player: &mut TDPlayer, iid: &[u64; 4]) -> Result<(), u32> {
+    check_not_delegated(iid[0])?;
+    if find_placed_tower(iid).is_some() {
+        return Err(ERROR_TOWER_ALREADY_PLACED);
+    }
+    let inventory_obj = InventoryObject::<GameDirection>::get(iid).unwrap();
+    let refund = (inventory_obj.spent as u128 * CONFIG.burn_refund_percent as u128 / 100) as u64;
+    player.data.reward += inventory_obj.reward + refund;
+    player.data.remove_tower(iid[0]);
+    let kvpair = unsafe { &mut MERKLE_MAP };
+    kvpair.set(iid, &[]);
+    let mut leaderboard = Leaderboard::get();
+    leaderboard.remove_tower(iid[0]);
+    leaderboard.store();
+    Ok(())
+}
+
+/// Fuses unplaced towers of the same kind and level into the first one, which gains a level
+/// for every other tower consumed and takes over their reward, kills and upgrade spend
+pub fn handle_fuse_towers(player: &mut TDPlayer, tower_ids: &[u64]) -> Result<(), u32> {
+    let mut inputs = vec![];
+    for (i, tower_id) in tower_ids.iter().enumerate() {
+        if tower_ids[..i].contains(tower_id) {
+            return Err(ERROR_FUSION_MISMATCH);
+        }
+        let iid = crate::game::object::to_full_obj_id(*tower_id);
+        if find_placed_tower(&iid).is_some() {
+            return Err(ERROR_TOWER_ALREADY_PLACED);
+        }
+        inputs.push(InventoryObject::<GameDirection>::get(&iid).unwrap());
+    }
+    let (output, consumed) = inputs.split_first_mut().unwrap();
+    let tower = output.object.get_the_tower_mut();
+    for input in consumed.iter() {
+        let t = input.object.get_the_tower();
+        if t.kind != tower.kind || t.lvl != tower.lvl {
+            return Err(ERROR_FUSION_MISMATCH);
+        }
+    }
+    // branched kinds level up through their recipes only
+    if tower.kind != TOWER_KIND_STANDARD {
+        return Err(ERROR_FUSION_MISMATCH);
+    }
+    for _ in consumed.iter() {
+        if !upgrade_tower(tower) {
+            return Err(ERROR_MAX_LEVEL);
+        }
+    }
+    let kvpair = unsafe { &mut MERKLE_MAP };
+    let mut leaderboard = Leaderboard::get();
+    for input in consumed.iter() {
+        output.reward += input.reward;
+        output.kills += input.kills;
+        output.spent += input.spent;
+        kvpair.set(&input.object_id, &[]);
+        player.data.remove_tower(input.object_id[0]);
+        leaderboard.remove_tower(input.object_id[0]);
+    }
+    if output.kills > 0 {
+        leaderboard.update_tower(output.object_id[0], output.kills);
+    }
+    leaderboard.store();
+    output.store();
+    Ok(())
+}
 
-pub fn handle_upgrade_inventory(iid: &[u64; 4]) {
-    //let global = unsafe { &mut crate::config::GLOBAL };
-    let mut inventory_obj = InventoryObject::get(iid).unwrap();
+/// Returns the level of the upgraded tower, the cost is paid from the tower reward
+/// unless a treasury is given
+pub fn handle_upgrade_inventory(iid: &[u64; 4], recipe: u64, treasury: Option<&mut u64>) -> Result<u64, u32> {
+    let mut inventory_obj = load_inventory_object(iid).unwrap();
+    let balance = match treasury {
+        Some(treasury) => treasury,
+        None => &mut inventory_obj.reward,
+    };
     let tower = inventory_obj.object.get_the_tower_mut();
-    unsafe {require(tower.lvl < 3)};
-    let cost = UPGRADE_COST[tower.lvl as usize];
-    unsafe {require(inventory_obj.reward >= cost)};
-    inventory_obj.reward -= cost;
-    inventory_obj.object.upgrade();
-    inventory_obj.store();
+    if recipe == RECIPE_LEVEL_UP {
+        if tower.kind != TOWER_KIND_STANDARD {
+            return Err(ERROR_RECIPE_NOT_APPLICABLE);
+        }
+        let cost = upgrade_cost(tower.lvl).ok_or(ERROR_MAX_LEVEL)?;
+        if *balance < cost {
+            return Err(ERROR_NOT_ENOUGH_REWARD);
+        }
+        *balance -= cost;
+        inventory_obj.spent += cost;
+        inventory_obj.object.upgrade();
+    } else {
+        let r = CONFIG
+            .recipes
+            .get((recipe - 1) as usize)
+            .ok_or(ERROR_UNKNOWN_RECIPE)?;
+        if !r.applies_to(tower) {
+            return Err(ERROR_RECIPE_NOT_APPLICABLE);
+        }
+        if *balance < r.cost {
+            return Err(ERROR_NOT_ENOUGH_REWARD);
+        }
+        r.apply(tower);
+        *balance -= r.cost;
+        inventory_obj.spent += r.cost;
+    }
+    store_inventory_object(&inventory_obj);
+    Ok(inventory_obj.object.get_the_tower().lvl)
+}
+
+/// A tower hitting a monster during one step
+struct Hit {
+    tower: usize,
+    damage: u64,
+    kill: bool,
+    reward: u64,
 }
 
-impl State {
+impl<C: Coordinate> State<C> {
+    /// Adds the hits of this step to the stats of the tower owners and credits the reward
+    /// of towers whose owner enabled auto collection, delegated towers keep their reward
+    /// until it is split on collection. The leaderboard is updated along the way.
+    fn settle_hits(&mut self, hits: Vec<Hit>) {
+        if hits.is_empty() {
+            return;
+        }
+        let delegations = Delegations::get();
+        let mut leaderboard = Leaderboard::get();
+        let mut owners: Vec<([u64; 2], Option<TDPlayer>)> = vec![];
+        let mut guilds: Vec<Guild> = vec![];
+        for hit in hits {
+            let inventory_obj = &mut self.towers[hit.tower].object;
+            if hit.kill {
+                leaderboard.update_tower(inventory_obj.object_id[0], inventory_obj.kills);
+            }
+            let pid = inventory_obj.object.get_the_tower().owner;
+            // rewards of guild towers go straight to the treasury once the rent owed is paid
+            if pid[1] == GUILD_OWNER {
+                inventory_obj.pay_rent_due();
+                let pos = match guilds.iter().position(|g| g.id == pid[0]) {
+                    Some(pos) => pos,
+                    None => {
+                        guilds.push(Guild::get(pid[0]).unwrap());
+                        guilds.len() - 1
+                    }
+                };
+                guilds[pos].treasury += inventory_obj.reward;
+                inventory_obj.reward = 0;
+                inventory_obj.store();
+                continue;
+            }
+            let pos = match owners.iter().position(|(p, _)| *p == pid) {
+                Some(pos) => pos,
+                None => {
+                    owners.push((pid, TDPlayer::get_from_pid(&pid)));
+                    owners.len() - 1
+                }
+            };
+            if let Some(owner) = owners[pos].1.as_mut() {
+                let stats = &mut owner.data.stats;
+                stats.hits += 1;
+                stats.kills += hit.kill as u64;
+                stats.damage += hit.damage;
+                stats.earned += hit.reward;
+                if hit.kill {
+                    let kills = stats.kills;
+                    check_achievements(owner, &pid, ACHIEVEMENT_KILLS, kills);
+                    check_achievements(owner, &pid, ACHIEVEMENT_TOWER_KILLS, inventory_obj.kills);
+                }
+                let delegated = delegations.0.iter().any(|d| d.tower_id == inventory_obj.object_id[0]);
+                if owner.data.auto_collect && !delegated {
+                    inventory_obj.pay_rent_due();
+                    owner.data.reward += inventory_obj.reward;
+                    inventory_obj.reward = 0;
+                    inventory_obj.store();
+                }
+            }
+        }
+        for (pid, owner) in owners.iter() {
+            if let Some(owner) = owner {
+                leaderboard.update_player(*pid, owner.data.stats.earned);
+                owner.store();
+            }
+        }
+        for guild in guilds.iter() {
+            guild.store();
+        }
+        leaderboard.store();
+    }
+
+    /// Charges the rent of every placed tower from its reward. Unpaid rent is owed by the
+    /// tower, which is removed from the board once it owes more than `rent_grace` payments.
+    fn charge_rent(&mut self) {
+        let rent = CONFIG.tower_rent;
+        if rent == 0 || current_tick() % CONFIG.rent_interval != 0 {
+            return;
+        }
+        // swap_remove from the end so that the towers left to visit keep their index
+        for index in (0..self.towers.len()).rev() {
+            let inventory_obj = &mut self.towers[index].object;
+            inventory_obj.rent_due += rent;
+            inventory_obj.pay_rent_due();
+            if inventory_obj.rent_due > rent * CONFIG.rent_grace {
+                inventory_obj.rent_due = 0;
+                inventory_obj.store();
+                let owner = inventory_obj.object.get_the_tower().owner;
+                let tower = self.remove_tower_at(index);
+                emit(Event::TowerEvicted(
+                    [owner[0].to_string(), owner[1].to_string()],
+                    tower.object.object_id[0].to_string(),
+                ));
+            } else {
+                inventory_obj.store();
+            }
+        }
+    }
+
+    /// Waves completed by the busiest spawner
+    pub fn wave(&self) -> u64 {
+        self.spawners.iter().map(|s| s.object.count).max().unwrap_or(0) / WAVE_SIZE
+    }
+
+    /// Owners of placed towers survive the wave
+    fn settle_wave(&self, wave: u64) {
+        let mut owners: Vec<[u64; 2]> = vec![];
+        for t in self.towers.iter() {
+            let pid = t.object.object.get_the_tower().owner;
+            if !owners.contains(&pid) {
+                owners.push(pid);
+                if let Some(mut owner) = TDPlayer::get_from_pid(&pid) {
+                    check_achievements(&mut owner, &pid, ACHIEVEMENT_WAVE, wave);
+                    owner.store();
+                }
+            }
+        }
+    }
+
     pub fn run(&mut self) {
         let splen = self.spawners.len();
         let mlen = self.monsters.len();
@@ -288,7 +681,7 @@ impl State {
         let mut termination_monster = vec![];
         let mut termination_drop = vec![];
         let mut spawn = vec![];
-        let mut tower_range: Vec<(Tower<RectDirection>, RectCoordinate, usize, usize, usize)> =
+        let mut tower_range: Vec<(Tower<C::Direction>, C, usize, usize, usize)> =
             vec![];
 
         for (index, obj) in self.monsters.iter_mut().enumerate() {
@@ -321,6 +714,7 @@ impl State {
             }
         }
 
+        let wave = self.wave();
         for (_index, obj) in self.spawners.iter_mut().enumerate() {
             let spawner = &mut obj.object;
             if spawner.rate == 0 {
@@ -356,29 +750,44 @@ impl State {
             }
         }
 
-        for (index, obj) in self.monsters.iter_mut().enumerate() {
-            for t in tower_range.iter_mut() {
-                let range = t.0.range(&t.1, &obj.position);
-                if range < t.2 {
-                    t.2 = range;
-                    t.4 = index;
+        // each ready tower hits the nearest monster in its line of sight and range
+        let monsters_by_tile = self.map.objects_by_tile(&self.monsters);
+        for t in tower_range.iter_mut() {
+            let ray = self.map.ray(&t.1, t.0.direction().clone());
+            for (steps, cor) in ray.iter().take(t.0.range as usize).enumerate() {
+                let tile = self.map.index_of_tile_coordinate(cor);
+                if let Some(index) = monsters_by_tile[tile].first() {
+                    t.2 = steps + 1;
+                    t.4 = *index;
+                    break;
                 }
             }
         }
 
         let mut events = vec![];
+        let mut hits = vec![];
 
         for t in tower_range.iter_mut() {
-            if t.4 != usize::max_value() {
+            // skip monsters already killed by another tower in this step
+            if t.4 != usize::max_value() && self.monsters[t.4].object.hp > 0 {
                 let m = &mut self.monsters[t.4].object;
                 let hit_reward = m.hit;
+                let mut hit = Hit {
+                    tower: t.3,
+                    damage: m.hp.min(t.0.power),
+                    kill: false,
+                    reward: hit_reward,
+                };
                 if m.hp < t.0.power {
                     m.hp = 0;
                 } else {
                     m.hp -= t.0.power;
                 }
                 if m.hp == 0 {
+                    hit.kill = true;
+                    hit.reward += m.kill;
                     self.towers[t.3].object.reward += m.kill; // kill reward
+                    self.towers[t.3].object.kills += 1;
                     termination_monster.push(t.4);
                     self.id_allocator += 1;
                     spawn.push(PositionedObject::new(
@@ -397,9 +806,16 @@ impl State {
                 }
                 self.towers[t.3].object.reward += hit_reward; // hit reward
                 self.towers[t.3].object.store();
+                hits.push(hit);
             }
         }
 
+        self.settle_hits(hits);
+        self.charge_rent();
+
+        // swap_remove from the highest index so that the other indices stay valid
+        termination_monster.sort();
+        termination_monster.dedup();
         termination_monster.reverse();
         for idx in termination_monster {
             self.remove_monster(idx);
@@ -407,13 +823,523 @@ impl State {
 
         termination_drop.reverse();
         for idx in termination_drop {
-            self.remove_monster(idx);
+            self.remove_dropped(idx);
         }
 
         for obj in spawn.into_iter() {
             self.spawn(obj);
         }
 
+        if self.wave() > wave {
+            self.settle_wave(self.wave());
+        }
+
         self.events = events;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::advance_tick;
+    use super::current_tick;
+    use super::find_placed_tower;
+    use super::handle_collect_all_rewards;
+    use super::handle_burn_tower;
+    use super::handle_fuse_towers;
+    use super::handle_place_tower;
+    use super::handle_transfer_tower;
+    use super::handle_upgrade_inventory;
+    use super::load_inventory_object;
+    use super::store_inventory_object;
+    use super::to_arena_key;
+    use super::Hit;
+    use super::State;
+    use crate::config::GameCoordinate;
+    use crate::config::ARENAS;
+    use crate::config::GameDirection;
+    use crate::config::CONFIG;
+    use crate::config::RECIPE_LEVEL_UP;
+    use crate::config::MAX_LEVEL;
+    use crate::config::TOWER_KIND_SNIPER;
+    use crate::config::TOWER_KIND_STANDARD;
+    use crate::game::leaderboard::Leaderboard;
+    use crate::game::ERROR_FUSION_MISMATCH;
+    use crate::game::ERROR_MAX_LEVEL;
+    use crate::game::object::to_full_obj_id;
+    use crate::game::object::InventoryObject;
+    use crate::game::object::Object;
+    use crate::game::ERROR_ARENA_NOT_FOUND;
+    use crate::game::ERROR_INVALID_RECIPIENT;
+    use crate::game::ERROR_POSITION_BLOCKED;
+    use crate::game::ERROR_POSITION_OCCUPIED;
+    use crate::game::ERROR_POSITION_ON_PATH;
+    use crate::game::ERROR_POSITION_RESERVED;
+    use crate::game::ERROR_RECIPE_NOT_APPLICABLE;
+    use crate::game::ERROR_TOWER_ALREADY_PLACED;
+    use crate::game::ERROR_TOWER_DELEGATED;
+    use crate::game::ERROR_TOWER_LIMIT;
+    use crate::game::ERROR_TOWERS_TOO_CLOSE;
+    use crate::game::ERROR_UNKNOWN_RECIPE;
+    use crate::config::build_tower;
+    use crate::game::delegation::Delegation;
+    use crate::game::delegation::Delegations;
+    use crate::game::guild::Guild;
+    use crate::game::guild::GUILD_OWNER;
+    use crate::game::object::Collector;
+    use crate::game::object::Dropped;
+    use crate::game::object::Monster;
+    use crate::game::object::Spawner;
+    use crate::game::event::Event;
+    use crate::game::event::EVENTS;
+    use crate::game::testing;
+    use crate::tile::coordinate::HexCoordinate;
+    use crate::tile::coordinate::HexDirection;
+    use crate::tile::coordinate::RectCoordinate;
+    use crate::tile::coordinate::RectDirection;
+    use crate::player::Owner;
+    use crate::player::TDPlayer;
+    use crate::tile::coordinate::Coordinate;
+    use crate::tile::coordinate::TileType;
+
+    fn tower(id: u64) -> InventoryObject<GameDirection> {
+        InventoryObject::new(to_full_obj_id(id), Object::Tower(CONFIG.standard_towers[0].clone()))
+    }
+
+    #[test]
+    fn test_place_tower_checks_tile_type() {
+        let mut state = State::<GameCoordinate>::new(0, 4, 1);
+        state.map.set_tile_type(1, TileType::Path);
+        state.map.set_tile_type(2, TileType::Blocked);
+        state.map.set_tile_type(3, TileType::Reserved);
+        let at = |index| state.map.coordinate_of_tile_index(index);
+        let (buildable, path, blocked, reserved) = (at(0), at(1), at(2), at(3));
+        assert_eq!(state.place_tower_at(tower(1), path).err(), Some(ERROR_POSITION_ON_PATH));
+        assert_eq!(state.place_tower_at(tower(1), blocked).err(), Some(ERROR_POSITION_BLOCKED));
+        assert_eq!(state.place_tower_at(tower(1), reserved).err(), Some(ERROR_POSITION_RESERVED));
+        assert!(state.towers.is_empty());
+        assert!(state.place_tower_at(tower(1), buildable.clone()).is_ok());
+        assert_eq!(state.place_tower_at(tower(2), buildable).err(), Some(ERROR_POSITION_OCCUPIED));
+        assert_eq!(state.towers.len(), 1);
+    }
+
+    #[test]
+    fn test_hex_arena_round_trip() {
+        let _guard = testing::setup();
+        let mut state = State::<HexCoordinate>::new(0xfe, 4, 3);
+        let at = |index| state.map.coordinate_of_tile_index(index);
+        let (spawner, monster, placed) = (at(0), at(5), at(10));
+        state.place_spawner_at(Spawner::new(0, 3), spawner);
+        state.spawn_monster_at(Monster::new(7, 2, 1), monster);
+        let mut tower = build_tower(2, HexDirection::BottomLeft);
+        tower.owner = [3, 4];
+        let object = InventoryObject::new(to_full_obj_id(9), Object::Tower(tower));
+        state.place_tower_at(object, placed.clone()).unwrap();
+        state.store();
+
+        let mut restored = State::<HexCoordinate>::new(0xfe, 4, 3);
+        assert!(restored.fetch());
+        assert_eq!(restored.id_allocator, 3);
+        assert_eq!(restored.map.index_of_tile_coordinate(&restored.spawners[0].position), 0);
+        assert_eq!(restored.monsters[0].object.hp, 7);
+        assert_eq!(restored.map.index_of_tile_coordinate(&restored.monsters[0].position), 5);
+        let stored = &restored.towers[0];
+        assert_eq!(restored.map.index_of_tile_coordinate(&stored.position), 10);
+        assert_eq!(stored.object.object_id, to_full_obj_id(9));
+        let tower = stored.object.object.get_the_tower();
+        assert_eq!((tower.lvl, tower.owner), (2, [3, 4]));
+        assert_eq!(u64::from(tower.direction().clone()), HexDirection::BottomLeft as u64);
+        assert_eq!(restored.map.get_occupy(&placed), 1);
+    }
+
+    #[test]
+    fn test_place_tower_in_last_arena() {
+        let _guard = testing::setup();
+        let pid = [1, 2];
+        testing::new_player(pid, 0);
+        testing::mint(1, pid);
+        let iid = to_full_obj_id(1);
+        let arenas = unsafe { &ARENAS };
+        let last = arenas.len() - 1;
+        let pos = testing::buildable_tile(last);
+        assert_eq!(handle_place_tower(&iid, arenas.len() as u64, pos), Err(ERROR_ARENA_NOT_FOUND));
+        handle_place_tower(&iid, last as u64, pos).unwrap();
+        assert_eq!(find_placed_tower(&iid), Some((last, 0)));
+        assert_eq!(handle_place_tower(&iid, 0, pos), Err(ERROR_TOWER_ALREADY_PLACED));
+
+        // every arena is stored under its own key
+        for arena in arenas.iter() {
+            arena.store();
+        }
+        for (id, arena) in arenas.iter().enumerate() {
+            assert_eq!(to_arena_key(id as u64), [id as u64, 0, 0, 0]);
+            let mut restored = State::<GameCoordinate>::new(id as u64, arena.map.width, arena.map.height);
+            assert!(restored.fetch());
+            assert_eq!(restored.spawners.len(), arena.spawners.len());
+            assert_eq!(restored.towers.len(), if id == last { 1 } else { 0 });
+        }
+    }
+
+    #[test]
+    fn test_upgrade_recipes() {
+        let _guard = testing::setup();
+        let pid = [1, 2];
+        testing::new_player(pid, 0);
+        testing::mint(1, pid);
+        let iid = to_full_obj_id(1);
+        let r = &CONFIG.recipes[0];
+        let mut obj = InventoryObject::<GameDirection>::get(&iid).unwrap();
+        obj.object.get_the_tower_mut().lvl = r.source_lvl;
+        obj.reward = r.cost + 5;
+        obj.store();
+        let unknown = CONFIG.recipes.len() as u64 + 1;
+        assert_eq!(handle_upgrade_inventory(&iid, unknown, None), Err(ERROR_UNKNOWN_RECIPE));
+        assert_eq!(handle_upgrade_inventory(&iid, 1, None), Ok(r.target_lvl));
+        let obj = InventoryObject::<GameDirection>::get(&iid).unwrap();
+        let tower = obj.object.get_the_tower();
+        assert_eq!((tower.kind, tower.range, tower.power, tower.cooldown), (r.target_kind, r.range, r.power, r.cooldown));
+        assert_eq!((obj.reward, obj.spent), (5, r.cost));
+        // the branch leaves the standard upgrades and its own source level behind
+        assert_eq!(handle_upgrade_inventory(&iid, RECIPE_LEVEL_UP, None), Err(ERROR_RECIPE_NOT_APPLICABLE));
+        assert_eq!(handle_upgrade_inventory(&iid, 1, None), Err(ERROR_RECIPE_NOT_APPLICABLE));
+    }
+
+    #[test]
+    fn test_transfer_tower() {
+        let _guard = testing::setup();
+        let (pkey, pid, to) = ([0, 1, 2, 0], [1, 2], [0x33, 0x44]);
+        testing::new_player(pid, 0);
+        testing::mint(1, pid);
+        testing::mint(2, pid);
+        let (iid, placed) = (to_full_obj_id(1), to_full_obj_id(2));
+        let mut obj = InventoryObject::<GameDirection>::get(&iid).unwrap();
+        obj.reward = 40;
+        obj.store();
+        handle_place_tower(&placed, 0, testing::buildable_tile(0)).unwrap();
+        let mut player = TDPlayer::get_from_pid(&pid).unwrap();
+
+        assert_eq!(handle_transfer_tower(&mut player, &pkey, &placed, &to), Err(ERROR_TOWER_ALREADY_PLACED));
+        assert_eq!(handle_transfer_tower(&mut player, &pkey, &iid, &pid), Err(ERROR_INVALID_RECIPIENT));
+        assert!(TDPlayer::get_from_pid(&to).is_none());
+        handle_transfer_tower(&mut player, &pkey, &iid, &to).unwrap();
+        assert!(!player.owns(1) && player.owns(2));
+        assert_eq!(player.data.reward, 40);
+        let recipient = TDPlayer::get_from_pid(&to).unwrap();
+        assert!(recipient.owns(1));
+        assert_eq!(recipient.nonce, 1);
+        let obj = InventoryObject::<GameDirection>::get(&iid).unwrap();
+        assert_eq!(obj.object.get_the_tower().owner, to);
+        assert_eq!(obj.reward, 0);
+    }
+
+    fn set_reward(tower_id: u64, reward: u64) {
+        let mut obj = load_inventory_object(&to_full_obj_id(tower_id)).unwrap();
+        obj.reward = reward;
+        store_inventory_object(&obj);
+    }
+
+    fn delegate(tower_id: u64, owner: [u64; 2], delegate: [u64; 2], share: u64) {
+        let mut delegations = Delegations::get();
+        delegations.0.push(Delegation { tower_id, owner, delegate, share, expires: 100 });
+        delegations.store();
+    }
+
+    #[test]
+    fn test_collect_all_rewards() {
+        let _guard = testing::setup();
+        let (pkey, pid, other) = ([0, 1, 2, 0], [1, 2], [3, 4]);
+        testing::new_player(pid, 0);
+        testing::new_player(other, 0);
+        for id in 1..=4 {
+            testing::mint(id, pid);
+        }
+        handle_place_tower(&to_full_obj_id(2), 0, testing::buildable_tile(0)).unwrap();
+        set_reward(1, 10);
+        set_reward(2, 20);
+        set_reward(4, 50);
+        delegate(4, pid, other, 40);
+        let mut player = TDPlayer::get_from_pid(&pid).unwrap();
+        handle_collect_all_rewards(&mut player, &pkey).unwrap();
+        // the placed tower is collected from the board, the delegated one is split
+        assert_eq!(player.data.reward, 10 + 20 + 30);
+        assert_eq!(TDPlayer::get_from_pid(&other).unwrap().data.reward, 20);
+        let arenas = unsafe { &ARENAS };
+        assert_eq!(arenas[0].towers[0].object.reward, 0);
+        for id in 1..=4 {
+            assert_eq!(InventoryObject::<GameDirection>::get(&to_full_obj_id(id)).unwrap().reward, 0);
+        }
+    }
+
+    #[test]
+    fn test_auto_collect_skips_delegated_towers() {
+        let _guard = testing::setup();
+        let pid = [1, 2];
+        let mut player = testing::new_player(pid, 0);
+        player.data.auto_collect = true;
+        player.store();
+        testing::mint(1, pid);
+        testing::mint(2, pid);
+        let tiles = testing::buildable_tiles(0);
+        handle_place_tower(&to_full_obj_id(1), 0, tiles[0]).unwrap();
+        handle_place_tower(&to_full_obj_id(2), 0, *tiles.last().unwrap()).unwrap();
+        delegate(2, pid, [3, 4], 50);
+        let arena = unsafe { &mut ARENAS[0] };
+        for tower in arena.towers.iter_mut() {
+            tower.object.reward = 10;
+        }
+        let hits = (0..2).map(|tower| Hit { tower, damage: 1, kill: false, reward: 10 }).collect();
+        arena.settle_hits(hits);
+        assert_eq!(TDPlayer::get_from_pid(&pid).unwrap().data.reward, 10);
+        let rewards = arena.towers.iter().map(|t| (t.object.object_id[0], t.object.reward)).collect::<Vec<_>>();
+        assert_eq!(rewards, vec![(1, 0), (2, 10)]);
+        assert_eq!(InventoryObject::<GameDirection>::get(&to_full_obj_id(1)).unwrap().reward, 0);
+
+        // without auto collection the reward stays with the towers
+        let mut player = TDPlayer::get_from_pid(&pid).unwrap();
+        player.data.auto_collect = false;
+        player.store();
+        arena.towers[0].object.reward = 10;
+        arena.settle_hits(vec![Hit { tower: 0, damage: 1, kill: false, reward: 10 }]);
+        assert_eq!(arena.towers[0].object.reward, 10);
+        assert_eq!(TDPlayer::get_from_pid(&pid).unwrap().data.reward, 10);
+    }
+
+    #[test]
+    fn test_guild_tower_rewards_go_to_treasury() {
+        let _guard = testing::setup();
+        Guild { id: 0x56, members: vec![], treasury: 0, towers: vec![1] }.store();
+        testing::mint(1, [0x56, 1]);
+        let mut obj = InventoryObject::<GameDirection>::get(&to_full_obj_id(1)).unwrap();
+        obj.object.get_the_tower_mut().owner = [0x56, GUILD_OWNER];
+        obj.store();
+        handle_place_tower(&to_full_obj_id(1), 0, testing::buildable_tile(0)).unwrap();
+        let arena = unsafe { &mut ARENAS[0] };
+        arena.towers[0].object.reward = 10;
+        arena.towers[0].object.rent_due = 4;
+        arena.settle_hits(vec![Hit { tower: 0, damage: 1, kill: false, reward: 10 }]);
+        // the rent owed is paid before the reward moves to the treasury
+        assert_eq!(Guild::get(0x56).unwrap().treasury, 6);
+        assert_eq!((arena.towers[0].object.reward, arena.towers[0].object.rent_due), (0, 0));
+    }
+
+    fn set_tower(tower_id: u64, kind: u64, lvl: u64, stats: [u64; 3]) {
+        let mut obj = InventoryObject::<GameDirection>::get(&to_full_obj_id(tower_id)).unwrap();
+        let tower = obj.object.get_the_tower_mut();
+        tower.kind = kind;
+        tower.lvl = lvl;
+        [obj.reward, obj.kills, obj.spent] = stats;
+        obj.store();
+    }
+
+    #[test]
+    fn test_fuse_tower_errors() {
+        let _guard = testing::setup();
+        let pid = [1, 2];
+        testing::new_player(pid, 0);
+        for id in 1..=4 {
+            testing::mint(id, pid);
+        }
+        let mut player = TDPlayer::get_from_pid(&pid).unwrap();
+        assert_eq!(handle_fuse_towers(&mut player, &[1, 1]), Err(ERROR_FUSION_MISMATCH));
+        assert_eq!(handle_fuse_towers(&mut player, &[1, 2, 1]), Err(ERROR_FUSION_MISMATCH));
+        set_tower(2, TOWER_KIND_STANDARD, 2, [0; 3]);
+        assert_eq!(handle_fuse_towers(&mut player, &[1, 2]), Err(ERROR_FUSION_MISMATCH));
+        set_tower(3, TOWER_KIND_SNIPER, 1, [0; 3]);
+        set_tower(4, TOWER_KIND_SNIPER, 1, [0; 3]);
+        assert_eq!(handle_fuse_towers(&mut player, &[3, 4]), Err(ERROR_FUSION_MISMATCH));
+        // two levels up from the level below max
+        set_tower(1, TOWER_KIND_STANDARD, MAX_LEVEL - 1, [0; 3]);
+        set_tower(2, TOWER_KIND_STANDARD, MAX_LEVEL - 1, [0; 3]);
+        set_tower(3, TOWER_KIND_STANDARD, MAX_LEVEL - 1, [0; 3]);
+        assert_eq!(handle_fuse_towers(&mut player, &[1, 2, 3]), Err(ERROR_MAX_LEVEL));
+        // nothing is consumed by a failed fusion
+        assert_eq!(player.data.inventory, vec![1, 2, 3, 4]);
+        assert!(InventoryObject::<GameDirection>::get(&to_full_obj_id(3)).is_some());
+    }
+
+    #[test]
+    fn test_fuse_towers_merges_inputs() {
+        let _guard = testing::setup();
+        let pid = [1, 2];
+        testing::new_player(pid, 0);
+        for id in 1..=3 {
+            testing::mint(id, pid);
+            set_tower(id, TOWER_KIND_STANDARD, 1, [id * 10, id, id * 100]);
+        }
+        let mut leaderboard = Leaderboard::get();
+        for id in 1..=3 {
+            leaderboard.update_tower(id, id);
+        }
+        leaderboard.store();
+        let mut player = TDPlayer::get_from_pid(&pid).unwrap();
+        handle_fuse_towers(&mut player, &[1, 2, 3]).unwrap();
+        let obj = InventoryObject::<GameDirection>::get(&to_full_obj_id(1)).unwrap();
+        assert_eq!(obj.object.get_the_tower().lvl, 3);
+        assert_eq!((obj.reward, obj.kills, obj.spent), (60, 6, 600));
+        assert!(InventoryObject::<GameDirection>::get(&to_full_obj_id(3)).is_none());
+        let towers = Leaderboard::get().towers.iter().map(|t| (t.tower_id, t.kills)).collect::<Vec<_>>();
+        assert_eq!(towers, vec![(1, 6)]);
+    }
+
+    #[test]
+    fn test_burn_tower() {
+        let _guard = testing::setup();
+        let pid = [1, 2];
+        testing::new_player(pid, 7);
+        for id in 1..=3 {
+            testing::mint(id, pid);
+        }
+        set_tower(1, TOWER_KIND_STANDARD, 2, [40, 3, 101]);
+        handle_place_tower(&to_full_obj_id(2), 0, testing::buildable_tile(0)).unwrap();
+        delegate(3, pid, [3, 4], 50);
+        let mut leaderboard = Leaderboard::get();
+        leaderboard.update_tower(1, 3);
+        leaderboard.store();
+
+        let mut player = TDPlayer::get_from_pid(&pid).unwrap();
+        assert_eq!(handle_burn_tower(&mut player, &to_full_obj_id(2)), Err(ERROR_TOWER_ALREADY_PLACED));
+        assert_eq!(handle_burn_tower(&mut player, &to_full_obj_id(3)), Err(ERROR_TOWER_DELEGATED));
+        handle_burn_tower(&mut player, &to_full_obj_id(1)).unwrap();
+        // pending reward plus the refund of the spend, rounded down
+        assert_eq!(player.data.reward, 7 + 40 + 101 * CONFIG.burn_refund_percent / 100);
+        assert!(InventoryObject::<GameDirection>::get(&to_full_obj_id(1)).is_none());
+        assert!(Leaderboard::get().towers.is_empty());
+    }
+
+    #[test]
+    fn test_charge_rent() {
+        let _guard = testing::setup();
+        let pid = [1, 2];
+        testing::new_player(pid, 100);
+        testing::mint(1, pid);
+        testing::mint(2, pid);
+        let tiles = testing::buildable_tiles(0);
+        handle_place_tower(&to_full_obj_id(1), 0, tiles[0]).unwrap();
+        handle_place_tower(&to_full_obj_id(2), 0, *tiles.last().unwrap()).unwrap();
+        let rent = CONFIG.tower_rent;
+        let arena = unsafe { &mut ARENAS[0] };
+        arena.towers[0].object.reward = rent;
+        // nothing is due between two rent intervals
+        advance_tick();
+        arena.charge_rent();
+        assert_eq!(arena.towers[0].object.reward, rent);
+        while current_tick() % CONFIG.rent_interval != 0 {
+            advance_tick();
+        }
+
+        // the tower without reward owes the rent until it is evicted after rent_grace payments,
+        // the balance of the owner is never charged
+        for _ in 0..CONFIG.rent_grace {
+            arena.charge_rent();
+        }
+        assert_eq!(arena.towers.len(), 2);
+        assert_eq!(arena.towers[1].object.rent_due, rent * CONFIG.rent_grace);
+        arena.charge_rent();
+        assert_eq!(arena.towers.len(), 1);
+        let obj = InventoryObject::<GameDirection>::get(&to_full_obj_id(2)).unwrap();
+        assert_eq!(obj.rent_due, 0);
+        let player = TDPlayer::get_from_pid(&pid).unwrap();
+        assert!(player.owns(2) && player.data.reward == 100);
+        assert!(matches!(unsafe { &EVENTS[..] }, [Event::TowerEvicted(_, id)] if id == "2"));
+    }
+
+    #[test]
+    fn test_tower_limit_and_distance() {
+        let _guard = testing::setup();
+        let pid = [1, 2];
+        let arena = unsafe { &ARENAS[0] };
+        let at = |index| arena.map.coordinate_of_tile_index(index);
+        let tiles = testing::buildable_tiles(0);
+        // buildable tiles far enough apart from each other
+        let mut spread: Vec<usize> = vec![];
+        for tile in tiles.iter() {
+            if spread.iter().all(|t| GameCoordinate::distance(&at(*t), &at(*tile)) >= CONFIG.min_tower_distance) {
+                spread.push(*tile);
+            }
+        }
+        let close = *tiles
+            .iter()
+            .find(|t| **t != spread[0] && GameCoordinate::distance(&at(spread[0]), &at(**t)) < CONFIG.min_tower_distance)
+            .unwrap();
+        let limit = CONFIG.max_towers_per_board;
+        for id in 1..=limit + 1 {
+            testing::mint(id, pid);
+        }
+        handle_place_tower(&to_full_obj_id(1), 0, spread[0]).unwrap();
+        let iid = to_full_obj_id(limit + 1);
+        assert_eq!(handle_place_tower(&iid, 0, close), Err(ERROR_TOWERS_TOO_CLOSE));
+        // other owners are not kept at a distance
+        testing::mint(limit + 2, [3, 4]);
+        handle_place_tower(&to_full_obj_id(limit + 2), 0, close).unwrap();
+
+        for id in 2..=limit {
+            handle_place_tower(&to_full_obj_id(id), 0, spread[id as usize - 1]).unwrap();
+        }
+        assert_eq!(handle_place_tower(&iid, 0, spread[limit as usize]), Err(ERROR_TOWER_LIMIT));
+    }
+
+    /// A level 1 tower that fires in the next step
+    fn rect_tower(id: u64, dir: RectDirection) -> InventoryObject<RectDirection> {
+        let mut tower = build_tower(1, dir);
+        tower.count = 0;
+        InventoryObject::new(to_full_obj_id(id), Object::Tower(tower))
+    }
+
+    #[test]
+    fn test_run_removes_collected_drops() {
+        let _guard = testing::setup();
+        let mut state = State::<RectCoordinate>::new(0xfe, 5, 5);
+        state.place_collector_at(Collector::new(0), RectCoordinate::new(3, 3));
+        state.spawn_monster_at(Monster::new(100, 1, 1), RectCoordinate::new(0, 4));
+        state.spawn_dropped_at(Dropped::new(10), RectCoordinate::new(3, 3));
+        state.run();
+        assert!(state.drops.is_empty());
+        assert_eq!(state.monsters.len(), 1);
+    }
+
+    #[test]
+    fn test_run_skips_monsters_killed_in_the_same_step() {
+        let _guard = testing::setup();
+        let mut state = State::<RectCoordinate>::new(0xfe, 5, 5);
+        state.place_tower_at(rect_tower(0x301, RectDirection::Right), RectCoordinate::new(0, 0)).unwrap();
+        state.place_tower_at(rect_tower(0x302, RectDirection::Left), RectCoordinate::new(4, 0)).unwrap();
+        // both towers see the first monster, the first tower kills it
+        state.spawn_monster_at(Monster::new(1, 1, 1), RectCoordinate::new(2, 0));
+        state.spawn_monster_at(Monster::new(100, 1, 1), RectCoordinate::new(2, 3));
+        state.run();
+        assert_eq!(state.monsters.len(), 1);
+        assert_eq!(state.monsters[0].position.repr(), (2, 3));
+        assert_eq!(state.drops.len(), 1);
+        assert_eq!(state.towers.iter().map(|t| t.object.kills).sum::<u64>(), 1);
+    }
+
+    #[test]
+    fn test_run_removes_monsters_killed_out_of_order() {
+        let _guard = testing::setup();
+        let mut state = State::<RectCoordinate>::new(0xfe, 5, 5);
+        state.spawn_monster_at(Monster::new(1, 1, 1), RectCoordinate::new(1, 2));
+        state.spawn_monster_at(Monster::new(100, 1, 1), RectCoordinate::new(4, 4));
+        state.spawn_monster_at(Monster::new(1, 1, 1), RectCoordinate::new(1, 0));
+        // the first tower kills the last monster, the second one the first monster
+        state.place_tower_at(rect_tower(0x303, RectDirection::Right), RectCoordinate::new(0, 0)).unwrap();
+        state.place_tower_at(rect_tower(0x304, RectDirection::Right), RectCoordinate::new(0, 2)).unwrap();
+        state.run();
+        assert_eq!(state.monsters.len(), 1);
+        assert_eq!(state.monsters[0].position.repr(), (4, 4));
+        assert_eq!(state.drops.len(), 2);
+    }
+
+    #[test]
+    fn test_run_hits_only_within_range() {
+        let _guard = testing::setup();
+        let mut state = State::<RectCoordinate>::new(0xfe, 8, 1);
+        state.place_tower_at(rect_tower(0x305, RectDirection::Right), RectCoordinate::new(0, 0)).unwrap();
+        let range = state.towers[0].object.object.get_the_tower().range as i64;
+        state.spawn_monster_at(Monster::new(100, 1, 1), RectCoordinate::new(range + 1, 0));
+        state.run();
+        assert_eq!(state.monsters[0].object.hp, 100);
+        state.spawn_monster_at(Monster::new(100, 1, 1), RectCoordinate::new(range, 0));
+        state.run();
+        assert_eq!(state.monsters[0].object.hp, 100);
+        assert!(state.monsters[1].object.hp < 100);
+    }
+}
@@ -0,0 +1,70 @@
+// Shared fixtures for the unit tests that go through the merkle map
+use super::event::EVENTS;
+use super::object::to_full_obj_id;
+use super::state::to_arena_key;
+use crate::config::init_state;
+use crate::config::ARENAS;
+use crate::player::TDPlayer;
+use crate::settlement::SettlementInfo;
+use crate::tile::coordinate::TileType;
+use crate::MERKLE_MAP;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+static LOCK: Mutex<()> = Mutex::new(());
+
+// tags of the singleton records stored at [0, 0xffff, 0xff01, tag]
+const SINGLETONS: [u64; 6] = [0xff03, 0xff04, 0xff05, 0xff06, 0xff07, 0xff0a];
+
+/// Serializes the tests on the global state and resets the game records
+pub fn setup() -> MutexGuard<'static, ()> {
+    let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    SettlementInfo::flush_settlement();
+    let kvpair = unsafe { &mut MERKLE_MAP };
+    for tag in SINGLETONS {
+        kvpair.set(&[0, 0xffff, 0xff01, tag], &[]);
+    }
+    unsafe {
+        for arena in ARENAS.iter() {
+            kvpair.set(&to_arena_key(arena.arena), &[]);
+        }
+        ARENAS.clear();
+        EVENTS.clear();
+    }
+    init_state();
+    guard
+}
+
+pub fn new_player(pid: [u64; 2], reward: u64) -> TDPlayer {
+    let mut player = TDPlayer::new_from_pid(pid);
+    player.data.reward = reward;
+    player.store();
+    player
+}
+
+/// Mints a fresh standard tower into the inventory of pid
+pub fn mint(id: u64, pid: [u64; 2]) {
+    let kvpair = unsafe { &mut MERKLE_MAP };
+    kvpair.set(&to_full_obj_id(id), &[]);
+    super::state::handle_update_inventory(&to_full_obj_id(id), 0, &pid).unwrap();
+}
+
+/// Indexes of the tiles of the given type in the arena
+pub fn tiles_of_type(arena: usize, tile_type: TileType) -> Vec<usize> {
+    let arenas = unsafe { &ARENAS };
+    let tiles = arenas[arena].map.tiles.iter().enumerate();
+    tiles.filter(|(_, t)| t.tile_type == tile_type).map(|(i, _)| i).collect()
+}
+
+/// Indexes of the tiles of the arena that take a tower
+pub fn buildable_tiles(arena: usize) -> Vec<usize> {
+    tiles_of_type(arena, TileType::Buildable)
+}
+
+pub fn buildable_tile(arena: usize) -> usize {
+    buildable_tiles(arena)[0]
+}
+
+pub fn cmd(c: u64, feature: u64, nonce: u64) -> u64 {
+    c | (feature << 8) | (nonce << 16)
+}
@@ -0,0 +1,321 @@
+use super::event::emit;
+use super::event::Event;
+use super::market::pid_serializer;
+use super::state::current_tick;
+use super::ERROR_INVALID_ADDRESS;
+use super::ERROR_INVALID_AMOUNT;
+use super::ERROR_NOT_ENOUGH_REWARD;
+use super::ERROR_WITHDRAW_LIMIT;
+use crate::config::CONFIG;
+use crate::player::TDPlayer;
+use crate::settlement::SettlementInfo;
+use crate::tx::WithdrawInfo;
+use crate::MERKLE_MAP;
+use serde::{Serialize, Serializer};
+
+const WITHDRAWALS_KEY: [u64; 4] = [0, 0xffff, 0xff01, 0xff06];
+
+pub struct PendingWithdrawal {
+    pub pid: [u64; 2],
+    pub info: WithdrawInfo,
+}
+
+impl Serialize for PendingWithdrawal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct View {
+            #[serde(serialize_with = "pid_serializer")]
+            pid: [u64; 2],
+            amount: u32,
+            address: String,
+        }
+        View {
+            pid: self.pid,
+            amount: self.info.amount,
+            address: self.info.address_hex(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Withdrawal usage of the current window and the withdrawals waiting for a later window
+#[derive(Serialize)]
+pub struct Withdrawals {
+    pub window: u64,
+    pub global_used: u64,
+    #[serde(skip_serializing)]
+    pub player_used: Vec<([u64; 2], u64)>,
+    pub pending: Vec<PendingWithdrawal>,
+    pub pending_total: u64,
+}
+
+/// The public view of the withdrawals, the queue itself is only shown to the operator
+#[derive(Serialize)]
+pub struct WithdrawalsSummary {
+    pub window: u64,
+    pub global_used: u64,
+    pub pending_count: u64,
+    pub pending_total: u64,
+}
+
+impl Withdrawals {
+    pub fn get() -> Self {
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        let data = kvpair.get(&WITHDRAWALS_KEY);
+        let mut u64data = data.iter();
+        let mut next = || u64data.next().map_or(0, |x| *x);
+        let window = next();
+        let global_used = next();
+        let used_len = next();
+        let player_used = (0..used_len).map(|_| ([next(), next()], next())).collect();
+        let pending_len = next();
+        let pending: Vec<PendingWithdrawal> = (0..pending_len)
+            .map(|_| PendingWithdrawal {
+                pid: [next(), next()],
+                info: WithdrawInfo::decode(&[next(), next(), next()]),
+            })
+            .collect();
+        let pending_total = pending.iter().map(|p| p.info.amount as u64).sum();
+        Withdrawals {
+            window,
+            global_used,
+            player_used,
+            pending,
+            pending_total,
+        }
+    }
+    pub fn store(&self) {
+        let mut data = vec![self.window, self.global_used, self.player_used.len() as u64];
+        for (pid, used) in self.player_used.iter() {
+            data.extend_from_slice(&[pid[0], pid[1], *used]);
+        }
+        data.push(self.pending.len() as u64);
+        for p in self.pending.iter() {
+            data.extend_from_slice(&[p.pid[0], p.pid[1]]);
+            data.extend_from_slice(&p.info.encode());
+        }
+        let kvpair = unsafe { &mut MERKLE_MAP };
+        kvpair.set(&WITHDRAWALS_KEY, data.as_slice());
+    }
+    pub fn summary(&self) -> WithdrawalsSummary {
+        WithdrawalsSummary {
+            window: self.window,
+            global_used: self.global_used,
+            pending_count: self.pending.len() as u64,
+            pending_total: self.pending_total,
+        }
+    }
+    fn used_by(&self, pid: &[u64; 2]) -> u64 {
+        self.player_used
+            .iter()
+            .find(|(p, _)| p == pid)
+            .map_or(0, |(_, used)| *used)
+    }
+    /// Settles the withdrawal if it fits into both caps of the current window
+    fn try_settle(&mut self, pid: &[u64; 2], info: &WithdrawInfo) -> bool {
+        let amount = info.amount as u64;
+        if self.global_used + amount > CONFIG.global_withdraw_cap
+            || self.used_by(pid) + amount > CONFIG.player_withdraw_cap
+        {
+            return false;
+        }
+        self.global_used += amount;
+        match self.player_used.iter_mut().find(|(p, _)| p == pid) {
+            Some((_, used)) => *used += amount,
+            None => self.player_used.push((*pid, amount)),
+        }
+        emit(Event::Withdraw(amount, info.address_hex()));
+        SettlementInfo::append_settlement(info.clone());
+        true
+    }
+    /// Starts a new window if the tick moved past the current one
+    fn roll_window(&mut self, tick: u64) -> bool {
+        let window = tick / CONFIG.withdraw_window;
+        if window == self.window {
+            return false;
+        }
+        self.window = window;
+        self.global_used = 0;
+        self.player_used = vec![];
+        true
+    }
+}
+
+/// Deducts the amount from the player and settles it, or queues it behind the queued
+/// withdrawals when there are any or a cap of the current window is reached
+pub fn handle_withdraw_rewards(player: &mut TDPlayer, pkey: &[u64; 4], limbs: &[u64; 3]) -> Result<(), u32> {
+    let info = WithdrawInfo::decode(limbs);
+    if info.amount == 0 {
+        return Err(ERROR_INVALID_AMOUNT);
+    }
+    if !info.is_valid() {
+        return Err(ERROR_INVALID_ADDRESS);
+    }
+    let amount = info.amount as u64;
+    if amount > CONFIG.player_withdraw_cap || amount > CONFIG.global_withdraw_cap {
+        return Err(ERROR_WITHDRAW_LIMIT);
+    }
+    if player.data.reward < amount {
+        return Err(ERROR_NOT_ENOUGH_REWARD);
+    }
+    let pid = TDPlayer::pkey_to_pid(pkey);
+    let mut withdrawals = Withdrawals::get();
+    withdrawals.roll_window(current_tick());
+    if withdrawals.pending.is_empty() && withdrawals.try_settle(&pid, &info) {
+        player.data.stats.withdrawn += amount;
+    } else {
+        if withdrawals.pending.len() as u64 >= CONFIG.max_pending_withdrawals {
+            return Err(ERROR_WITHDRAW_LIMIT);
+        }
+        emit(Event::WithdrawQueued(amount, info.address_hex()));
+        withdrawals.pending_total += amount;
+        withdrawals.pending.push(PendingWithdrawal { pid, info });
+    }
+    player.data.reward -= amount;
+    withdrawals.store();
+    Ok(())
+}
+
+/// Settles queued withdrawals in order once a new window starts, the tick advances by one
+/// per step so the withdrawals are only loaded on the first tick of a window. The settled
+/// amounts count as withdrawn in the stats of the players.
+pub fn process_withdrawals(tick: u64) {
+    if tick % CONFIG.withdraw_window != 0 {
+        return;
+    }
+    let mut withdrawals = Withdrawals::get();
+    if !withdrawals.roll_window(tick) {
+        return;
+    }
+    let pending = std::mem::take(&mut withdrawals.pending);
+    let mut blocked: Vec<[u64; 2]> = vec![];
+    for p in pending.into_iter() {
+        if blocked.contains(&p.pid) || !withdrawals.try_settle(&p.pid, &p.info) {
+            blocked.push(p.pid);
+            withdrawals.pending.push(p);
+        } else {
+            let mut player = TDPlayer::get_from_pid(&p.pid).unwrap();
+            player.data.stats.withdrawn += p.info.amount as u64;
+            player.store();
+        }
+    }
+    withdrawals.store();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::handle_withdraw_rewards;
+    use super::process_withdrawals;
+    use super::Withdrawals;
+    use super::ERROR_INVALID_AMOUNT;
+    use super::ERROR_WITHDRAW_LIMIT;
+    use crate::config::CONFIG;
+    use crate::game::testing;
+    use crate::settlement::SettlementInfo;
+
+    fn limbs(amount: u64) -> [u64; 3] {
+        [amount, 0, 1]
+    }
+
+    fn withdraw(pid: [u64; 2], amount: u64) -> Result<(), u32> {
+        let pkey = [0, pid[0], pid[1], 0];
+        let mut player = crate::player::TDPlayer::get_from_pid(&pid).unwrap();
+        handle_withdraw_rewards(&mut player, &pkey, &limbs(amount))?;
+        player.store();
+        Ok(())
+    }
+
+    fn settled() -> usize {
+        SettlementInfo::flush_settlement().len() / 32
+    }
+
+    /// Uses up the global cap of the current window
+    fn fill_global_cap() {
+        for i in 0..CONFIG.global_withdraw_cap / CONFIG.player_withdraw_cap {
+            let pid = [100 + i, 0];
+            testing::new_player(pid, CONFIG.player_withdraw_cap);
+            withdraw(pid, CONFIG.player_withdraw_cap).unwrap();
+        }
+        assert_eq!(Withdrawals::get().global_used, CONFIG.global_withdraw_cap);
+        settled();
+    }
+
+    #[test]
+    fn test_player_cap_and_window_rollover() {
+        let _guard = testing::setup();
+        let (pid, other) = ([1, 2], [3, 4]);
+        let cap = CONFIG.player_withdraw_cap;
+        testing::new_player(pid, 3 * cap);
+        testing::new_player(other, 5);
+        assert_eq!(withdraw(pid, 0), Err(ERROR_INVALID_AMOUNT));
+        assert_eq!(withdraw(pid, cap + 1), Err(ERROR_WITHDRAW_LIMIT));
+        withdraw(pid, cap - 100).unwrap();
+        assert_eq!(settled(), 1);
+        // over the cap of the window, the later withdrawal queues behind the first one
+        withdraw(pid, 200).unwrap();
+        withdraw(pid, 50).unwrap();
+        // fits into the window, but queues behind the withdrawals of the other player
+        withdraw(other, 5).unwrap();
+        assert_eq!(settled(), 0);
+        let player = crate::player::TDPlayer::get_from_pid(&pid).unwrap();
+        assert_eq!(player.data.reward, 3 * cap - (cap - 100) - 250);
+        // only settled withdrawals count as withdrawn
+        assert_eq!(player.data.stats.withdrawn, cap - 100);
+        let summary = Withdrawals::get().summary();
+        assert_eq!((summary.window, summary.pending_count, summary.pending_total), (0, 3, 255));
+
+        process_withdrawals(CONFIG.withdraw_window - 1);
+        assert_eq!(Withdrawals::get().pending.len(), 3);
+        process_withdrawals(CONFIG.withdraw_window);
+        assert_eq!(settled(), 3);
+        let withdrawals = Withdrawals::get();
+        assert!(withdrawals.pending.is_empty());
+        assert_eq!((withdrawals.window, withdrawals.global_used), (1, 255));
+        assert_eq!(withdrawals.used_by(&pid), 250);
+        let player = crate::player::TDPlayer::get_from_pid(&pid).unwrap();
+        assert_eq!(player.data.stats.withdrawn, cap + 150);
+        assert_eq!(crate::player::TDPlayer::get_from_pid(&other).unwrap().data.stats.withdrawn, 5);
+    }
+
+    #[test]
+    fn test_global_cap_and_queue_draining() {
+        let _guard = testing::setup();
+        fill_global_cap();
+        let (a, b) = ([1, 2], [3, 4]);
+        let cap = CONFIG.player_withdraw_cap;
+        testing::new_player(a, cap + 10);
+        testing::new_player(b, 5);
+        withdraw(a, cap).unwrap();
+        withdraw(a, 10).unwrap();
+        withdraw(b, 5).unwrap();
+        assert_eq!(settled(), 0);
+        assert_eq!(Withdrawals::get().summary().pending_total, cap + 15);
+
+        // the second withdrawal of a exceeds its cap and waits, b is not held up by it
+        process_withdrawals(CONFIG.withdraw_window);
+        assert_eq!(settled(), 2);
+        let pending = Withdrawals::get().pending;
+        assert_eq!(pending.len(), 1);
+        assert_eq!((pending[0].pid, pending[0].info.amount), (a, 10));
+        process_withdrawals(2 * CONFIG.withdraw_window);
+        assert_eq!(settled(), 1);
+        assert!(Withdrawals::get().pending.is_empty());
+    }
+
+    #[test]
+    fn test_queue_is_bounded() {
+        let _guard = testing::setup();
+        fill_global_cap();
+        let pid = [1, 2];
+        let max = CONFIG.max_pending_withdrawals;
+        testing::new_player(pid, max + 1);
+        for _ in 0..max {
+            withdraw(pid, 1).unwrap();
+        }
+        assert_eq!(withdraw(pid, 1), Err(ERROR_WITHDRAW_LIMIT));
+        assert_eq!(crate::player::TDPlayer::get_from_pid(&pid).unwrap().data.reward, 1);
+        assert_eq!(Withdrawals::get().pending.len() as u64, max);
+        process_withdrawals(CONFIG.withdraw_window);
+        assert_eq!(settled() as u64, max);
+    }
+}
@@ -10,3 +10,33 @@ pub mod tx;
 use crate::config::Config;
 use crate::game::{State, Transaction};
 zkwasm_rest_abi::create_zkwasm_apis!(Transaction, State, Config);
+
+#[wasm_bindgen]
+pub fn query_market() -> String {
+    State::get_market()
+}
+
+#[wasm_bindgen]
+pub fn query_delegations() -> String {
+    State::get_delegations()
+}
+
+#[wasm_bindgen]
+pub fn query_withdrawals() -> String {
+    State::get_withdrawals()
+}
+
+#[wasm_bindgen]
+pub fn query_leaderboard() -> String {
+    State::get_leaderboard()
+}
+
+#[wasm_bindgen]
+pub fn query_tower_metadata(tower_id: u64) -> String {
+    State::get_tower_metadata(tower_id)
+}
+
+#[wasm_bindgen]
+pub fn query_auctions() -> String {
+    State::get_auctions()
+}
@@ -1,3 +1,5 @@
+use crate::config::CONFIG;
+use crate::game::ERROR_INVENTORY_FULL;
 use crate::StorageData;
 use crate::Player;
 use core::slice::IterMut;
@@ -15,11 +17,27 @@ where
     seq.end()
 }
 
+/// Lifetime statistics of a player, hits and rewards count for the towers the player owns
+#[derive(Clone, Default, Serialize)]
+pub struct PlayerStats {
+    pub kills: u64,
+    pub hits: u64,
+    pub damage: u64,
+    pub earned: u64,
+    pub withdrawn: u64,
+    pub towers_placed: u64,
+}
+
 #[derive(Clone, Serialize)]
 pub struct PlayerData {
     #[serde(serialize_with = "bigint_array_serializer")]
     pub inventory: Vec<u64>,
     pub reward: u64,
+    pub auto_collect: bool, // credit tower rewards to the balance during each step
+    pub stats: PlayerStats,
+    pub achievements: u64, // bit n is set once achievement n of the config is unlocked
+    #[serde(serialize_with = "crate::game::bigint_serializer")]
+    pub guild: u64, // 0 if the player is not in a guild
 }
 
 impl Default for PlayerData {
@@ -27,6 +45,10 @@ impl Default for PlayerData {
         Self {
             inventory: vec![],
             reward:0,
+            auto_collect: false,
+            stats: PlayerStats::default(),
+            achievements: 0,
+            guild: 0,
         }
     }
 }
@@ -38,9 +60,33 @@ impl StorageData for PlayerData {
         for _ in 0..objects_size {
             inventory.push(*u64data.next().unwrap());
         }
+        // players stored before the inventory was kept sorted, sorted and without
+        // duplicates again on the next store
+        if !inventory.windows(2).all(|w| w[0] < w[1]) {
+            inventory.sort_unstable();
+            inventory.dedup();
+        }
+        let reward = *u64data.next().unwrap();
+        // fields below were added later and default to zero for older players
+        let mut next = || u64data.next().map_or(0, |x| *x);
+        let auto_collect = next() != 0;
+        let stats = PlayerStats {
+            kills: next(),
+            hits: next(),
+            damage: next(),
+            earned: next(),
+            withdrawn: next(),
+            towers_placed: next(),
+        };
+        let achievements = next();
+        let guild = next();
         PlayerData {
             inventory,
-            reward: (*u64data.next().unwrap())
+            reward,
+            auto_collect,
+            stats,
+            achievements,
+            guild,
         }
     }
     fn to_data(&self, data: &mut Vec<u64>) {
@@ -49,6 +95,52 @@ impl StorageData for PlayerData {
             data.push(*c as u64);
         }
         data.push(self.reward);
+        data.push(self.auto_collect as u64);
+        data.push(self.stats.kills);
+        data.push(self.stats.hits);
+        data.push(self.stats.damage);
+        data.push(self.stats.earned);
+        data.push(self.stats.withdrawn);
+        data.push(self.stats.towers_placed);
+        data.push(self.achievements);
+        data.push(self.guild);
+    }
+}
+
+impl PlayerData {
+    /// Sets the bit of the achievement, false if it was already unlocked
+    /// or does not fit into the bitset
+    pub fn unlock(&mut self, achievement: u64) -> bool {
+        if achievement >= u64::BITS as u64 {
+            return false;
+        }
+        let bit = 1 << achievement;
+        let new = self.achievements & bit == 0;
+        self.achievements |= bit;
+        new
+    }
+
+    /// Inserts the tower keeping the inventory sorted, fails once the inventory is full
+    pub fn add_tower(&mut self, tower_id: u64) -> Result<(), u32> {
+        if let Err(index) = self.inventory.binary_search(&tower_id) {
+            if self.inventory.len() as u64 >= CONFIG.inventory_cap {
+                return Err(ERROR_INVENTORY_FULL);
+            }
+            self.inventory.insert(index, tower_id);
+        }
+        Ok(())
+    }
+
+    pub fn remove_tower(&mut self, tower_id: u64) {
+        if let Ok(index) = self.inventory.binary_search(&tower_id) {
+            self.inventory.remove(index);
+        }
+    }
+
+    /// The towers on the given page of the inventory, empty past the last page
+    pub fn inventory_page(&self, page: usize) -> Vec<u64> {
+        let page_size = CONFIG.inventory_page_size as usize;
+        self.inventory.chunks(page_size).nth(page).map_or(vec![], |c| c.to_vec())
     }
 }
 
@@ -64,11 +156,86 @@ impl Owner for TDPlayer {
         TDPlayer::get_from_pid(&TDPlayer::pkey_to_pid(pkey))
     }
     fn owns(&self, tower_id: u64) -> bool {
-        for o in self.data.inventory.iter() {
-            if *o == tower_id {
-                return true;
-            }
+        self.data.inventory.binary_search(&tower_id).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PlayerData;
+    use crate::config::CONFIG;
+    use crate::game::ERROR_INVENTORY_FULL;
+    use crate::StorageData;
+
+    #[test]
+    fn test_unlock() {
+        let mut data = PlayerData::default();
+        assert!(data.unlock(0));
+        assert!(!data.unlock(0));
+        assert!(data.unlock(63));
+        assert_eq!(data.achievements, 1 | 1 << 63);
+        assert!(!data.unlock(64));
+        assert!(!data.unlock(u64::MAX));
+        assert_eq!(data.achievements, 1 | 1 << 63);
+    }
+
+    #[test]
+    fn test_decode_player_without_stats() {
+        // [inventory size, inventory..., reward] as stored before the stats were added
+        let mut stored = vec![2, 3, 5, 40];
+        let data = PlayerData::from_data(&mut stored.iter_mut());
+        assert_eq!(data.inventory, vec![3, 5]);
+        assert_eq!(data.reward, 40);
+        assert!(!data.auto_collect);
+        let s = &data.stats;
+        assert_eq!([s.kills, s.hits, s.damage, s.earned, s.withdrawn, s.towers_placed], [0; 6]);
+        assert_eq!((data.achievements, data.guild), (0, 0));
+    }
+
+    #[test]
+    fn test_add_tower_keeps_inventory_sorted() {
+        let mut data = PlayerData::default();
+        for id in [5, 1, 9, 3, 5] {
+            data.add_tower(id).unwrap();
+        }
+        assert_eq!(data.inventory, vec![1, 3, 5, 9]);
+        data.remove_tower(3);
+        data.remove_tower(4);
+        assert_eq!(data.inventory, vec![1, 5, 9]);
+    }
+
+    #[test]
+    fn test_inventory_cap() {
+        let mut data = PlayerData::default();
+        let cap = CONFIG.inventory_cap;
+        for id in (0..cap).rev() {
+            data.add_tower(id * 2).unwrap();
         }
-        return false;
+        assert_eq!(data.add_tower(1), Err(ERROR_INVENTORY_FULL));
+        // a tower already in the inventory is not added again
+        assert_eq!(data.add_tower(0), Ok(()));
+        assert_eq!(data.inventory.len() as u64, cap);
+        assert!(data.inventory.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_sort_legacy_inventory() {
+        let mut stored = vec![5, 9, 2, 9, 5, 2, 40];
+        let data = PlayerData::from_data(&mut stored.iter_mut());
+        assert_eq!(data.inventory, vec![2, 5, 9]);
+        let mut encoded = vec![];
+        data.to_data(&mut encoded);
+        assert_eq!(encoded[0..5], [3, 2, 5, 9, 40]);
+    }
+
+    #[test]
+    fn test_inventory_page() {
+        let size = CONFIG.inventory_page_size;
+        let mut data = PlayerData::default();
+        data.inventory = (0..size * 2 + 1).collect();
+        assert_eq!(data.inventory_page(0), (0..size).collect::<Vec<_>>());
+        assert_eq!(data.inventory_page(1), (size..size * 2).collect::<Vec<_>>());
+        assert_eq!(data.inventory_page(2), vec![size * 2]);
+        assert!(data.inventory_page(3).is_empty());
     }
 }
@@ -1,33 +1,45 @@
 use zkwasm_rest_abi::MERKLE_MAP;
 
-use crate::config::GLOBAL;
-pub struct SettlementInfo(Vec<[u64; 3]>);
+use crate::config::ARENAS;
+use crate::tx::TowerWithdrawInfo;
+use crate::tx::WithdrawInfo;
+
+pub enum Settlement {
+    Withdraw(WithdrawInfo),
+    WithdrawTower(TowerWithdrawInfo),
+}
+
+pub struct SettlementInfo(Vec<Settlement>);
 
 const WITHDRAW_OPCODE:[u8; 8] = [1, 0, 0, 0, 0, 0, 0, 0];
+const NFT_WITHDRAW_OPCODE:[u8; 8] = [2, 0, 0, 0, 0, 0, 0, 0];
 
 
 pub static mut SETTLEMENT: SettlementInfo = SettlementInfo(vec![]);
 
 impl SettlementInfo {
-    pub fn append_settlement(info: [u64; 3]) {
-        unsafe { SETTLEMENT.0.push(info) };
+    pub fn append_settlement(info: WithdrawInfo) {
+        unsafe { SETTLEMENT.0.push(Settlement::Withdraw(info)) };
+    }
+    pub fn append_tower_settlement(info: TowerWithdrawInfo) {
+        unsafe { SETTLEMENT.0.push(Settlement::WithdrawTower(info)) };
     }
     pub fn flush_settlement() -> Vec<u8> {
         zkwasm_rust_sdk::dbg!("flush settlement\n");
         let sinfo = unsafe { &mut SETTLEMENT };
         let mut bytes: Vec<u8> = Vec::with_capacity(sinfo.0.len() * 32);
+        // each record is the opcode followed by the withdrawal,
+        // 32 bytes for rewards and 96 bytes for towers
         for settlement in &sinfo.0 {
-            for i in WITHDRAW_OPCODE {
-                bytes.push(i)
-            }
-            for i in settlement[0].to_le_bytes() {
-                bytes.push(i)
-            }
-            for i in settlement[1].to_le_bytes() {
-                bytes.push(i)
-            }
-            for i in settlement[2].to_le_bytes() {
-                bytes.push(i)
+            match settlement {
+                Settlement::Withdraw(info) => {
+                    bytes.extend_from_slice(&WITHDRAW_OPCODE);
+                    bytes.extend_from_slice(&info.to_bytes());
+                }
+                Settlement::WithdrawTower(info) => {
+                    bytes.extend_from_slice(&NFT_WITHDRAW_OPCODE);
+                    bytes.extend_from_slice(&info.to_bytes());
+                }
             }
         }
         sinfo.0 = vec![];
@@ -35,10 +47,64 @@ impl SettlementInfo {
         let root = merkle_ref.merkle.root;
         zkwasm_rust_sdk::dbg!("pre merkle: {:?}", root);
 
-        unsafe { GLOBAL.store() };
+        for arena in unsafe { ARENAS.iter() } {
+            arena.store();
+        }
         let merkle_ref = unsafe {&mut MERKLE_MAP};
         let root = merkle_ref.merkle.root;
         zkwasm_rust_sdk::dbg!("post merkle: {:?}", root);
         bytes
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::SettlementInfo;
+    use crate::tx::TowerWithdrawInfo;
+    use crate::tx::WithdrawInfo;
+
+    #[test]
+    fn test_withdraw_settlement_bytes() {
+        let _guard = crate::game::testing::setup();
+        let address: [u8; 20] = core::array::from_fn(|i| i as u8 + 1);
+        let limbs = [
+            0x0403020100000064,
+            u64::from_le_bytes(address[4..12].try_into().unwrap()),
+            u64::from_le_bytes(address[12..20].try_into().unwrap()),
+        ];
+        let info = WithdrawInfo::decode(&limbs);
+        assert_eq!(info.amount, 100);
+        assert_eq!(info.address, address);
+        assert!(info.is_valid());
+        assert_eq!(info.encode(), limbs);
+        assert_eq!(info.address_hex(), "0x0102030405060708090a0b0c0d0e0f1011121314");
+
+        SettlementInfo::append_settlement(info);
+        SettlementInfo::append_settlement(WithdrawInfo::decode(&[0xffffffff, 0, 0x1400000000000000]));
+        let bytes = SettlementInfo::flush_settlement();
+        let mut expected = vec![1, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0];
+        expected.extend_from_slice(&address);
+        expected.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+        expected.extend_from_slice(&[0; 19]);
+        expected.push(0x14);
+        assert_eq!(bytes, expected);
+        assert!(SettlementInfo::flush_settlement().is_empty());
+
+        let info = TowerWithdrawInfo::decode(&limbs, [0xab; 32]);
+        assert_eq!(info.tower_id, 100);
+        SettlementInfo::append_tower_settlement(info);
+        let bytes = SettlementInfo::flush_settlement();
+        let mut expected = vec![2, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0];
+        expected.extend_from_slice(&address);
+        expected.extend_from_slice(&[0; 28]);
+        expected.extend_from_slice(&[0xab; 32]);
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn test_withdraw_validation() {
+        assert!(!WithdrawInfo::decode(&[0x0100000000000000, 0, 0]).is_valid());
+        assert!(!WithdrawInfo::decode(&[5, 0, 0]).is_valid());
+        assert!(WithdrawInfo::decode(&[5, 0, 1]).is_valid());
+    }
+}
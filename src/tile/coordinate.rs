@@ -1,6 +1,6 @@
 use serde::Serialize;
-pub trait Coordinate: Sized + PartialEq + Serialize {
-    type Direction: Clone + Serialize;
+pub trait Coordinate: Sized + Clone + PartialEq + Serialize {
+    type Direction: Clone + Serialize + Into<u64> + From<u64>;
     fn adjacents(&self) -> Vec<Self>;
     fn directions() -> Vec<Self::Direction>;
     fn adjacent(&self, direction: Self::Direction) -> Self;
@@ -19,6 +19,18 @@ pub enum HexDirection {
     Left,
 }
 
+impl From<HexDirection> for u64 {
+    fn from(d: HexDirection) -> u64 {
+        d as u64
+    }
+}
+
+impl From<u64> for HexDirection {
+    fn from(u: u64) -> HexDirection {
+        HexCoordinate::directions()[u as usize].clone()
+    }
+}
+
 #[derive(Clone, Serialize, PartialEq)]
 pub struct HexCoordinate {
     x: i64,
@@ -104,6 +116,18 @@ pub enum RectDirection {
     Left,
 }
 
+impl From<RectDirection> for u64 {
+    fn from(d: RectDirection) -> u64 {
+        d as u64
+    }
+}
+
+impl From<u64> for RectDirection {
+    fn from(u: u64) -> RectDirection {
+        RectCoordinate::directions()[u as usize].clone()
+    }
+}
+
 #[derive(Clone, Serialize, PartialEq)]
 pub struct RectCoordinate {
     x: i64,
@@ -159,10 +183,101 @@ impl Coordinate for RectCoordinate {
     }
 }
 
+#[derive(Clone, Serialize)]
+pub enum Rect8Direction {
+    Top,
+    TopRight,
+    Right,
+    BottomRight,
+    Bottom,
+    BottomLeft,
+    Left,
+    TopLeft,
+}
+
+impl From<Rect8Direction> for u64 {
+    fn from(d: Rect8Direction) -> u64 {
+        d as u64
+    }
+}
+
+impl From<u64> for Rect8Direction {
+    fn from(u: u64) -> Rect8Direction {
+        Rect8Coordinate::directions()[u as usize].clone()
+    }
+}
+
+/// Rectangular grid where monsters and bullets can also move diagonally
+#[derive(Clone, Serialize, PartialEq)]
+pub struct Rect8Coordinate {
+    x: i64,
+    y: i64,
+}
+
+impl Coordinate for Rect8Coordinate {
+    type Direction = Rect8Direction;
+
+    fn new(x: i64, y: i64) -> Self {
+        Rect8Coordinate { x, y }
+    }
+    fn repr(&self) -> (i64, i64) {
+        (self.x, self.y)
+    }
+    // a diagonal step costs the same as a straight one (Chebyshev distance)
+    fn distance(p1: &Self, p2: &Self) -> u64 {
+        let diff_y = (p1.y - p2.y).abs() as u64;
+        let diff_x = (p1.x - p2.x).abs() as u64;
+        diff_x.max(diff_y)
+    }
+    fn adjacents(&self) -> Vec<Self> {
+        Self::directions()
+            .into_iter()
+            .map(|d| self.adjacent(d))
+            .collect()
+    }
+    fn directions() -> Vec<Self::Direction> {
+        vec![
+            Self::Direction::Top,
+            Self::Direction::TopRight,
+            Self::Direction::Right,
+            Self::Direction::BottomRight,
+            Self::Direction::Bottom,
+            Self::Direction::BottomLeft,
+            Self::Direction::Left,
+            Self::Direction::TopLeft,
+        ]
+    }
+    fn adjacent(&self, direction: Self::Direction) -> Self {
+        use Rect8Direction::*;
+        let y = match direction {
+            TopLeft | Top | TopRight => self.y - 1,
+            Left | Right => self.y,
+            BottomLeft | Bottom | BottomRight => self.y + 1,
+        };
+        let x = match direction {
+            TopLeft | Left | BottomLeft => self.x - 1,
+            Top | Bottom => self.x,
+            TopRight | Right | BottomRight => self.x + 1,
+        };
+
+        Self::new(x, y)
+    }
+}
+
+#[derive(Clone, Copy, Serialize, PartialEq)]
+pub enum TileType {
+    Buildable, // towers can be placed here
+    Path,      // monsters walk through here
+    Blocked,   // neither towers nor monsters
+    Reserved,  // kept aside by the game, not open to players
+    Premium,   // auctioned, only the holder of the lease can build here
+}
+
 #[derive(Clone, Serialize)]
 pub struct Tile<F: Clone> {
     pub feature: F,
     pub occupied: u32,
+    pub tile_type: TileType,
 }
 
 impl<F: Clone> Tile<F> {
@@ -170,17 +285,23 @@ impl<F: Clone> Tile<F> {
         Tile {
             feature: f,
             occupied: 0,
+            tile_type: TileType::Buildable,
         }
     }
     pub fn set_feature(&mut self, f: F) {
         self.feature = f
     }
+    pub fn set_tile_type(&mut self, t: TileType) {
+        self.tile_type = t
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::Coordinate;
     use super::HexCoordinate;
+    use super::Rect8Coordinate;
+    use super::Rect8Direction;
 
     #[test]
     fn test_distance() {
@@ -198,4 +319,53 @@ mod tests {
         assert_eq!(dis14, 2);
         assert_eq!(dis15, 5);
     }
+
+    #[test]
+    fn test_rect8_distance() {
+        let p1 = Rect8Coordinate::new(0, 0);
+        let p2 = Rect8Coordinate::new(1, 2);
+        let p3 = Rect8Coordinate::new(3, 3);
+        let p4 = Rect8Coordinate::new(-4, 1);
+        assert_eq!(Rect8Coordinate::distance(&p1, &p2), 2);
+        assert_eq!(Rect8Coordinate::distance(&p1, &p3), 3);
+        assert_eq!(Rect8Coordinate::distance(&p1, &p4), 4);
+        assert_eq!(Rect8Coordinate::distance(&p3, &p4), 7);
+    }
+
+    #[test]
+    fn test_rect8_adjacents() {
+        let p = Rect8Coordinate::new(2, 2);
+        let adjacents = p.adjacents();
+        assert_eq!(adjacents.len(), 8);
+        let expected = [(2, 1), (3, 1), (3, 2), (3, 3), (2, 3), (1, 3), (1, 2), (1, 1)];
+        for (adjacent, cor) in adjacents.iter().zip(expected.iter()) {
+            assert_eq!(adjacent.repr(), *cor);
+            assert_eq!(Rect8Coordinate::distance(&p, adjacent), 1);
+        }
+    }
+
+    #[test]
+    fn test_rect8_directions() {
+        let p = Rect8Coordinate::new(0, 0);
+        let directions = Rect8Coordinate::directions();
+        assert_eq!(directions.len(), 8);
+        for (i, direction) in directions.into_iter().enumerate() {
+            let encoded: u64 = direction.clone().into();
+            assert_eq!(encoded, i as u64);
+            assert!(p.adjacent(direction.clone()) == p.adjacents()[i]);
+            assert!(p.adjacent(Rect8Direction::from(encoded)) == p.adjacent(direction));
+        }
+    }
+
+    #[test]
+    fn test_rect8_diagonal_steps() {
+        let p = Rect8Coordinate::new(1, 1);
+        let target = Rect8Coordinate::new(4, 4);
+        let walk = |from: &Rect8Coordinate, direction: Rect8Direction| {
+            (0..3).fold(from.clone(), |c, _| c.adjacent(direction.clone()))
+        };
+        assert!(walk(&p, Rect8Direction::BottomRight) == target);
+        assert!(walk(&p, Rect8Direction::Right) != target);
+        assert!(walk(&target, Rect8Direction::TopLeft) == p);
+    }
 }
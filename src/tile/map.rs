@@ -1,5 +1,6 @@
 use super::coordinate::Coordinate;
 use super::coordinate::Tile;
+use super::coordinate::TileType;
 use crate::game::bigint_serializer;
 use crate::game::object::Object;
 use serde::Serialize;
@@ -24,13 +25,14 @@ impl<C: Coordinate, O: Clone> PositionedObject<C, O> {
     }
 }
 
+// both halves are kept as i32 so that negative offsets survive the round trip
 fn cor_to_u64<C: Coordinate>(c: &C) -> u64 {
     let (x, y) = c.repr();
-    ((x as u64) << 32) + ((y as u32) as u64)
+    (((x as u32) as u64) << 32) + ((y as u32) as u64)
 }
 
 fn u64_to_cor<C: Coordinate>(u: u64) -> C {
-    C::new((u >> 32) as i64, (u & 0xffffffff) as i64)
+    C::new(((u >> 32) as u32 as i32) as i64, (u as u32 as i32) as i64)
 }
 
 
@@ -86,32 +88,92 @@ impl<C: Coordinate> Map<C> {
         self.tiles.get(index).unwrap().feature.clone()
     }
 
+    pub fn set_tile_type(&mut self, index: usize, t: TileType) {
+        self.tiles.get_mut(index).unwrap().set_tile_type(t)
+    }
+
+    pub fn get_tile_type(&self, cor: &C) -> TileType {
+        let index = self.index_of_tile_coordinate(cor);
+        self.tiles.get(index).unwrap().tile_type
+    }
+
     pub fn set_occupy(&mut self, cor: &C, indicator: u32) {
         let index = self.index_of_tile_coordinate(cor);
         self.tiles.get_mut(index).unwrap().occupied = indicator;
     }
 
-    pub fn get_occupy(&mut self, cor: &C) -> u32 {
+    pub fn get_occupy(&self, cor: &C) -> u32 {
         let index = self.index_of_tile_coordinate(cor);
-        self.tiles.get_mut(index).unwrap().occupied
+        self.tiles.get(index).unwrap().occupied
     }
 
-    /*
-    pub fn get_neighbours<O: Clone>(
-        &mut self,
-        pos: &PositionedObject<C, O>,
-        distance: u64,
-        filter: impl Fn(&PositionedObject<C, O>) -> bool,
-    ) -> Vec<&PositionedObject<C, O>> {
+    pub fn contains(&self, cor: &C) -> bool {
+        let (x, y) = cor.repr();
+        x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+    }
+
+    /// Tiles seen from src when looking along direction, stops at the border
+    /// of the map or right before the first blocked tile
+    pub fn ray(&self, src: &C, direction: C::Direction) -> Vec<C> {
         let mut r = vec![];
-        for obj in self.objects.iter() {
-            if C::distance(&obj.position, &pos.position) < distance {
-                if filter(obj) {
-                    r.push(obj)
-                }
-            }
+        let mut cursor = src.adjacent(direction.clone());
+        while self.contains(&cursor) && self.get_tile_type(&cursor) != TileType::Blocked {
+            let next = cursor.adjacent(direction.clone());
+            r.push(cursor);
+            cursor = next;
         }
         r
     }
-    */
+
+    /// For each tile the indexes of the objects standing on it, collected on every call
+    pub fn objects_by_tile<O: Clone>(&self, objects: &[PositionedObject<C, O>]) -> Vec<Vec<usize>> {
+        let mut index = vec![vec![]; self.tiles.len()];
+        for (i, obj) in objects.iter().enumerate() {
+            if self.contains(&obj.position) {
+                index[self.index_of_tile_coordinate(&obj.position)].push(i);
+            }
+        }
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Map;
+    use super::PositionedObject;
+    use crate::tile::coordinate::Coordinate;
+    use crate::tile::coordinate::RectCoordinate;
+    use crate::tile::coordinate::RectDirection;
+    use crate::tile::coordinate::Tile;
+    use crate::tile::coordinate::TileType;
+
+    fn new_map() -> Map<RectCoordinate> {
+        Map::new(5, 5, (0..25).map(|_| Tile::new(None)).collect())
+    }
+
+    #[test]
+    fn test_ray() {
+        let mut map = new_map();
+        let src = RectCoordinate::new(0, 2);
+        assert_eq!(map.ray(&src, RectDirection::Right).len(), 4);
+        assert_eq!(map.ray(&src, RectDirection::Left).len(), 0);
+        map.set_tile_type(map.index_of_tile_coordinate(&RectCoordinate::new(3, 2)), TileType::Blocked);
+        let ray = map.ray(&src, RectDirection::Right);
+        assert_eq!(ray.len(), 2);
+        assert!(ray[1] == RectCoordinate::new(2, 2));
+    }
+
+    #[test]
+    fn test_objects_by_tile() {
+        let map = new_map();
+        let objects = vec![
+            PositionedObject::new(0, RectCoordinate::new(1, 1), 1),
+            PositionedObject::new(0, RectCoordinate::new(4, 0), 2),
+            PositionedObject::new(0, RectCoordinate::new(1, 1), 3),
+        ];
+        let index = map.objects_by_tile(&objects);
+        assert_eq!(index[6], vec![0, 2]);
+        assert_eq!(index[4], vec![1]);
+        assert_eq!(index.iter().flatten().count(), 3);
+    }
 }
@@ -13,12 +13,29 @@ pub struct DepositInfo {
     pub sender: [u64; 4],
 }
 
+/// A withdrawal of reward to an L1 address
+#[derive(Clone, Debug, PartialEq)]
 pub struct WithdrawInfo {
-    pub opinfo: u64,
-    pub account_index: u32,
-    pub object_index: u32,
-    pub amount: [u64; 4],
-    pub sender: [u64; 4],
+    pub amount: u32,
+    pub address: [u8; 20],
+}
+
+/// A withdrawal of a tower to an L1 address as an NFT
+#[derive(Clone, Debug, PartialEq)]
+pub struct TowerWithdrawInfo {
+    pub tower_id: u64,
+    pub address: [u8; 20],
+    pub metadata_hash: [u8; 32],
+}
+
+/// Splits limbs into the low 32 bits of limbs[0] and a 20-byte address made of the high
+/// 32 bits of limbs[0], limbs[1] and limbs[2], all little endian
+fn decode_address(limbs: &[u64; 3]) -> (u32, [u8; 20]) {
+    let mut address = [0; 20];
+    address[0..4].copy_from_slice(&limbs[0].to_le_bytes()[4..8]);
+    address[4..12].copy_from_slice(&limbs[1].to_le_bytes());
+    address[12..20].copy_from_slice(&limbs[2].to_le_bytes());
+    ((limbs[0] & 0xffffffff) as u32, address)
 }
 
 pub fn read_tx_info<'a, T>(data: &'a [u64; 10]) -> &'a T {
@@ -26,7 +43,6 @@ pub fn read_tx_info<'a, T>(data: &'a [u64; 10]) -> &'a T {
 }
 
 const DEPOSIT: u8 = 0x0;
-const WITHDRAW: u8 = 0x1;
 
 impl DepositInfo {
     pub fn new(
@@ -50,22 +66,61 @@ impl DepositInfo {
 }
 
 impl WithdrawInfo {
-    pub fn new(
-        nounce: u64,
-        account_index: u32,
-        object_index: u32,
-        amount: [u64; 4],
-        sender: [u64; 4],
-    ) -> Self {
-        WithdrawInfo {
-            opinfo: (WITHDRAW as u64) + (nounce << 8),
-            account_index,
-            object_index,
-            amount,
-            sender,
+    /// limbs[0] holds the amount in its low 32 bits and address bytes 0..4 in its high 32 bits,
+    /// limbs[1] and limbs[2] hold address bytes 4..12 and 12..20, all little endian
+    pub fn decode(limbs: &[u64; 3]) -> Self {
+        let (amount, address) = decode_address(limbs);
+        WithdrawInfo { amount, address }
+    }
+    pub fn encode(&self) -> [u64; 3] {
+        let mut low = [0; 8];
+        low[0..4].copy_from_slice(&self.amount.to_le_bytes());
+        low[4..8].copy_from_slice(&self.address[0..4]);
+        [
+            u64::from_le_bytes(low),
+            u64::from_le_bytes(self.address[4..12].try_into().unwrap()),
+            u64::from_le_bytes(self.address[12..20].try_into().unwrap()),
+        ]
+    }
+    pub fn is_valid(&self) -> bool {
+        self.amount != 0 && self.address != [0; 20]
+    }
+    /// The amount as 4 little endian bytes followed by the address
+    pub fn to_bytes(&self) -> [u8; 24] {
+        let mut bytes = [0; 24];
+        bytes[0..4].copy_from_slice(&self.amount.to_le_bytes());
+        bytes[4..24].copy_from_slice(&self.address);
+        bytes
+    }
+    pub fn address_hex(&self) -> String {
+        let mut hex = String::from("0x");
+        for b in self.address.iter() {
+            hex.push_str(&format!("{:02x}", b));
         }
+        hex
     }
-    pub fn to_bytes(&self) -> &[u8; 80] {
-        unsafe { std::mem::transmute(self) }
+}
+
+impl TowerWithdrawInfo {
+    /// Same layout as WithdrawInfo with the tower id in place of the amount,
+    /// mint keeps tower ids below MAX_TOWER_ID so that they fit into the 32 bits
+    pub fn decode(limbs: &[u64; 3], metadata_hash: [u8; 32]) -> Self {
+        let (tower_id, address) = decode_address(limbs);
+        TowerWithdrawInfo {
+            tower_id: tower_id as u64,
+            address,
+            metadata_hash,
+        }
+    }
+    pub fn is_valid(&self) -> bool {
+        self.address != [0; 20]
+    }
+    /// The tower id as 8 little endian bytes, the address, 28 zero bytes and the metadata hash
+    pub fn to_bytes(&self) -> [u8; 88] {
+        let mut bytes = [0; 88];
+        bytes[0..8].copy_from_slice(&self.tower_id.to_le_bytes());
+        bytes[8..28].copy_from_slice(&self.address);
+        bytes[56..88].copy_from_slice(&self.metadata_hash);
+        bytes
     }
 }